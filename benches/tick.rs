@@ -0,0 +1,291 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+//! Times `Game::tick` on crowded boards and compares region capture,
+//! cash-in and enemy capture against the implementations they replaced.
+//! Run with `cargo bench`.
+
+extern crate dazzle;
+extern crate rand;
+
+use dazzle::common::*;
+use dazzle::game::{Game, GameConfig};
+use dazzle::grid::Grid;
+use rand::{Isaac64Rng, Rng, SeedableRng};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+const TICKS: u64 = 300;
+const SNAPSHOT_EVERY: u64 = 10;
+
+/// Region capture as done before the flat grid, kept as the reference
+fn legacy_capture(grid: &mut [Vec<Option<u64>>], walls: &[Vec<bool>])
+{
+    let mut visited: HashSet<Point> = Default::default();
+    let cols = grid[0].len();
+    let rows = grid.len();
+    for y in 0..rows
+    {
+        for x in 0..cols
+        {
+            if visited.contains(&Point { x, y }) || grid[y][x].is_some() || walls[y][x]
+            {
+                continue;
+            }
+            let mut to_fill: HashSet<Point> = Default::default();
+            let mut colors: HashSet<u64> = Default::default();
+            let mut queue: VecDeque<Point> = Default::default();
+            let mut valid = true;
+            queue.push_back(Point { x, y });
+            while let Some(p) = queue.pop_front()
+            {
+                if walls[p.y][p.x]
+                {
+                    continue;
+                }
+                if let Some(color) = grid[p.y][p.x]
+                {
+                    colors.insert(color);
+                    continue;
+                }
+                if to_fill.contains(&p)
+                {
+                    continue;
+                }
+                to_fill.insert(p.clone());
+                visited.insert(p.clone());
+                if p.x + 1 >= cols || p.x == 0 || p.y + 1 >= rows || p.y == 0
+                {
+                    valid = false;
+                    break;
+                }
+                queue.push_back(Point { x: p.x + 1, y: p.y });
+                queue.push_back(Point { x: p.x - 1, y: p.y });
+                queue.push_back(Point { x: p.x, y: p.y + 1 });
+                queue.push_back(Point { x: p.x, y: p.y - 1 });
+            }
+            if valid && colors.len() == 1
+            {
+                let color = colors.drain().last().unwrap();
+                for p in to_fill.drain()
+                {
+                    grid[p.y][p.x] = Some(color);
+                }
+            }
+        }
+    }
+}
+
+/// Cash-in counting as done before the per-player cell lists
+fn legacy_count(grid: &[Vec<Option<u64>>], id: u64) -> usize
+{
+    grid.iter().flat_map(|row| row.iter()).filter(|c| **c == Some(id)).count()
+}
+
+/// Enemy capture as done before the single-owner areas: one flood fill per color
+fn legacy_enclosures(grid: &Grid, colors: &[u64]) -> BTreeMap<usize, u64>
+{
+    let (rows, cols) = (grid.rows(), grid.cols());
+    let open = |p: &Point| !grid.is_wall(p.x, p.y);
+    let mut claims: BTreeMap<usize, (usize, u64)> = Default::default();
+    for &id in colors
+    {
+        let mut seen = vec![false; rows * cols];
+        for &cell in grid.cells_of(id)
+        {
+            let p = grid.point(cell);
+            for start in neighbours(&p, rows, cols)
+            {
+                let s = grid.index(start.x, start.y);
+                if seen[s] || !open(&start) || grid.get(start.x, start.y) == Some(id)
+                {
+                    continue;
+                }
+                seen[s] = true;
+                let mut stack = vec![start];
+                let mut area: Vec<usize> = Vec::new();
+                let mut enclosed = true;
+                while let Some(q) = stack.pop()
+                {
+                    area.push(grid.index(q.x, q.y));
+                    enclosed &= q.x > 0 && q.y > 0 && q.x + 1 < cols && q.y + 1 < rows;
+                    for n in neighbours(&q, rows, cols)
+                    {
+                        let i = grid.index(n.x, n.y);
+                        if !seen[i] && open(&n) && grid.get(n.x, n.y) != Some(id)
+                        {
+                            seen[i] = true;
+                            stack.push(n);
+                        }
+                    }
+                }
+                if enclosed
+                {
+                    for &i in &area
+                    {
+                        let claim = claims.entry(i).or_insert((area.len(), id));
+                        *claim = (*claim).min((area.len(), id));
+                    }
+                }
+            }
+        }
+    }
+    claims.into_iter().map(|(i, (_, id))| (i, id)).collect()
+}
+
+fn neighbours(p: &Point, rows: usize, cols: usize) -> Vec<Point>
+{
+    let mut list: Vec<Point> = Vec::new();
+    if p.x > 0
+    {
+        list.push(Point { x: p.x - 1, y: p.y });
+    }
+    if p.x + 1 < cols
+    {
+        list.push(Point { x: p.x + 1, y: p.y });
+    }
+    if p.y > 0
+    {
+        list.push(Point { x: p.x, y: p.y - 1 });
+    }
+    if p.y + 1 < rows
+    {
+        list.push(Point { x: p.x, y: p.y + 1 });
+    }
+    list
+}
+
+fn ms(d: Duration) -> f64
+{
+    d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0
+}
+
+/// Plays a game of random moves, returning it with the time spent in `tick`
+/// and a snapshot of the board every `SNAPSHOT_EVERY` ticks
+fn play(num_players: u64, rules: &str) -> (Game, Duration, Vec<Grid>)
+{
+    let mut players: BTreeMap<u64, Player> = Default::default();
+    for id in 1..num_players + 1
+    {
+        players.insert(id, Player::new(id, format!("p{}", id)));
+    }
+    let config = GameConfig {
+        turns: TICKS,
+        seed: num_players,
+        rules: String::from(rules),
+        ..Default::default()
+    };
+    let mut game = Game::new(players, 0, config).unwrap();
+    let mut rng = Isaac64Rng::from_seed(&[num_players]);
+    let directions = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+    let mut snapshots: Vec<Grid> = Vec::new();
+    let mut tick_time = Duration::new(0, 0);
+    while !game.finished()
+    {
+        for id in 1..num_players + 1
+        {
+            let direction = rng.choose(&directions).unwrap().clone();
+            let _ = game.action(id, ClientCommand::Move(direction));
+        }
+        let start = Instant::now();
+        game.tick();
+        tick_time += start.elapsed();
+        if game.tick.is_multiple_of(SNAPSHOT_EVERY)
+        {
+            snapshots.push(game.grid.clone());
+        }
+    }
+    (game, tick_time, snapshots)
+}
+
+fn bench(num_players: u64)
+{
+    let (game, tick_time, snapshots) = play(num_players, "classic");
+    let mut flat_time = Duration::new(0, 0);
+    let mut legacy_time = Duration::new(0, 0);
+    let mut flat_count = Duration::new(0, 0);
+    let mut legacy_count_time = Duration::new(0, 0);
+    for snapshot in &snapshots
+    {
+        let walls: Vec<Vec<bool>> = (0..snapshot.rows())
+            .map(|y| (0..snapshot.cols()).map(|x| snapshot.is_wall(x, y)).collect())
+            .collect();
+        let mut nested = snapshot.to_rows();
+        let start = Instant::now();
+        legacy_capture(&mut nested, &walls);
+        legacy_time += start.elapsed();
+        let mut flat = snapshot.clone();
+        let start = Instant::now();
+        flat.capture();
+        flat_time += start.elapsed();
+        assert!(flat.to_rows() == nested, "capture differs from the reference");
+        let start = Instant::now();
+        let legacy_total: usize = (1..num_players + 1).map(|id| legacy_count(&nested, id)).sum();
+        legacy_count_time += start.elapsed();
+        let start = Instant::now();
+        let flat_total: usize = (1..num_players + 1).map(|id| flat.count(id)).sum();
+        flat_count += start.elapsed();
+        assert_eq!(flat_total, legacy_total);
+    }
+    let samples = snapshots.len() as f64;
+    println!("{} players, {}x{} board", num_players, game.grid.rows(), game.grid.cols());
+    println!("  tick            {:>10.4} ms", ms(tick_time) / TICKS as f64);
+    println!("  capture         {:>10.4} ms, legacy {:>10.4} ms, {:.1}x",
+             ms(flat_time) / samples,
+             ms(legacy_time) / samples,
+             ms(legacy_time) / ms(flat_time));
+    println!("  count all       {:>10.4} ms, legacy {:>10.4} ms, {:.1}x",
+             ms(flat_count) / samples,
+             ms(legacy_count_time) / samples,
+             ms(legacy_count_time) / ms(flat_count).max(1e-6));
+}
+
+fn bench_enemy_capture(num_players: u64)
+{
+    let (game, tick_time, _) = play(num_players, "enemy-capture");
+    // Boards of classic games keep the loops around enemy cells, that enemy capture would take
+    let (_, _, snapshots) = play(num_players, "classic");
+    let colors: Vec<u64> = (1..num_players + 1).collect();
+    let mut taken = 0;
+    let mut flat_time = Duration::new(0, 0);
+    let mut legacy_time = Duration::new(0, 0);
+    for snapshot in &snapshots
+    {
+        let start = Instant::now();
+        let legacy = legacy_enclosures(snapshot, &colors);
+        legacy_time += start.elapsed();
+        let mut flat = snapshot.clone();
+        let start = Instant::now();
+        let claims = flat.enclosures(&colors);
+        flat_time += start.elapsed();
+        taken += claims.len();
+        assert!(claims.into_iter().collect::<BTreeMap<usize, u64>>() == legacy,
+                "enemy capture differs from the reference");
+    }
+    let samples = snapshots.len() as f64;
+    println!("{} players, {}x{} board, enemy capture", num_players, game.grid.rows(), game.grid.cols());
+    println!("  tick            {:>10.4} ms", ms(tick_time) / TICKS as f64);
+    println!("  enclosures      {:>10.4} ms, legacy {:>10.4} ms, {:.1}x, {:.0} cells taken",
+             ms(flat_time) / samples,
+             ms(legacy_time) / samples,
+             ms(legacy_time) / ms(flat_time),
+             taken as f64 / samples);
+}
+
+fn main()
+{
+    for &players in &[100, 200, 400]
+    {
+        bench(players);
+    }
+    for &players in &[100, 200, 400]
+    {
+        bench_enemy_capture(players);
+    }
+}
@@ -0,0 +1,345 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use chasher::{game_seed, player_hash};
+use common::*;
+use game::{BoardConfig, Game, GameConfig, GhostPolicy, TokenConfig};
+use guest::Guest;
+use map::Map;
+use powerup::PowerUpConfig;
+use replay::{Replay, ReplayEvent};
+use rules::DEFAULT_RULES;
+use std::collections::BTreeMap;
+use team;
+
+/// A series of games between local programs
+pub struct ArenaConfig
+{
+    pub games: u64,
+    pub turns: u64,
+    pub token_rate: f64,
+    pub seed: u64,
+    pub ms_for_turn: u64,
+    pub replay_dir: Option<String>,
+    pub rules: String,
+    pub board: BoardConfig,
+    pub tokens: TokenConfig,
+    pub power_ups: PowerUpConfig,
+    pub teams: usize,
+    pub maps: Vec<Map>,
+    pub programs: Vec<String>,
+}
+
+impl Default for ArenaConfig
+{
+    fn default() -> ArenaConfig
+    {
+        ArenaConfig {
+            games: 1,
+            turns: 300,
+            token_rate: 2.5,
+            seed: 0,
+            ms_for_turn: 500,
+            replay_dir: None,
+            rules: String::from(DEFAULT_RULES),
+            board: Default::default(),
+            tokens: Default::default(),
+            power_ups: Default::default(),
+            teams: 0,
+            maps: Vec::new(),
+            programs: Vec::new(),
+        }
+    }
+}
+
+/// A program taking part in the arena; `guest` is `None` once it dropped out
+pub struct Contestant
+{
+    pub guest: Option<Guest>,
+    pub player: Player,
+    pub wins: u64,
+    pub total_points: u64,
+}
+
+impl Contestant
+{
+    pub fn spawn(command_line: &str) -> Result<Contestant, String>
+    {
+        let words: Vec<String> = command_line.split_whitespace().map(String::from).collect();
+        if words.is_empty()
+        {
+            return Err(String::from("Empty program"));
+        }
+        let mut guest = Guest::spawn(&words[0], &words[1..]).map_err(|why| format!("Cannot start {}: {}", words[0], why))?;
+        let info = PlayerInfo {
+            name: String::from(guest.read_name().map_err(|why| format!("{}", why))?.trim()),
+            room: None,
+            token: None,
+            session: None,
+            team: None,
+        };
+        if info.name.is_empty() || "\n\r\t ".chars().any(|x| info.name.contains(x))
+        {
+            return Err(format!("Invalid name from {}", command_line));
+        }
+        Ok(Contestant {
+               guest: Some(guest),
+               player: Player::new(player_hash(&info), info.name.clone()),
+               wins: 0,
+               total_points: 0,
+           })
+    }
+}
+
+/// Sends the overview to the guests of the players still in the game and reads back their commands
+fn ask_moves(game: &Game,
+             overview: &Overview,
+             contestants: &mut BTreeMap<u64, Contestant>)
+             -> Vec<(u64, Result<Option<ClientCommand>, String>)>
+{
+    let mut commands = Vec::new();
+    for player in game.players.values().filter(|p| p.active)
+    {
+        let guest = match contestants.get_mut(&player.id).unwrap().guest
+        {
+            Some(ref mut guest) => guest,
+            None => continue,
+        };
+        let command = match guest.send_overview(overview)
+        {
+            Ok(_) => guest.read_command(),
+            Err(why) => Err(format!("{}", why)),
+        };
+        commands.push((player.id, command));
+    }
+    commands
+}
+
+/// Plays one game between the contestants that did not drop out
+pub fn play(config: &ArenaConfig,
+            contestants: &mut BTreeMap<u64, Contestant>,
+            game_id: u64)
+            -> Result<(GameEnded, Vec<TeamScore>), String>
+{
+    let mut players: BTreeMap<u64, Player> = Default::default();
+    for (id, c) in contestants.iter().filter(|&(_, c)| c.guest.is_some())
+    {
+        players.insert(*id, c.player.clone());
+    }
+    let mut board = config.board.clone();
+    if !config.maps.is_empty()
+    {
+        config.maps[game_id as usize % config.maps.len()].apply(&mut board);
+    }
+    let game_config = GameConfig {
+        turns: config.turns,
+        token_rate: config.token_rate,
+        seed: game_seed(config.seed, game_id),
+        ghost_policy: GhostPolicy::Freeze,
+        rules: config.rules.clone(),
+        board,
+        tokens: config.tokens.clone(),
+        power_ups: config.power_ups.clone(),
+    };
+    let mut replay = Replay::new(game_id, game_config.clone(), &players);
+    let mut game = Game::new(players, game_id, game_config).map_err(|why| format!("Cannot start game #{}: {}", game_id, why))?;
+    game.tick();
+    replay.push_frame(game.overview(config.ms_for_turn));
+    while !game.finished()
+    {
+        let commands = ask_moves(&game, &replay.frames.last().unwrap().overview, contestants);
+        for (id, command) in commands
+        {
+            let contestant = contestants.get_mut(&id).unwrap();
+            match command
+            {
+                Ok(Some(command)) =>
+                {
+                    if game.action(id, command.clone()).is_ok()
+                    {
+                        replay.record(ReplayEvent::Command(id, command));
+                    }
+                    continue;
+                }
+                Ok(None) => info!("{} quit", contestant.player.name),
+                Err(why) => error!("{} dropped out: {}", contestant.player.name, why),
+            }
+            contestant.guest = None;
+            game.leave(id);
+            replay.record(ReplayEvent::Leave(id));
+        }
+        if game.finished()
+        {
+            break;
+        }
+        game.tick();
+        replay.push_frame(game.overview(config.ms_for_turn));
+    }
+    if let Some(ref dir) = config.replay_dir
+    {
+        if let Err(why) = replay.save(dir)
+        {
+            error!("Unable to save replay: {}", why);
+        }
+    }
+    let ended = game.game_ended().unwrap();
+    for contestant in contestants.values_mut()
+    {
+        let failed = match contestant.guest
+        {
+            Some(ref mut guest) => guest.send_game_ended(&ended).is_err(),
+            None => false,
+        };
+        if failed
+        {
+            error!("{} dropped out", contestant.player.name);
+            contestant.guest = None;
+        }
+    }
+    Ok((ended, team::scores(&game)))
+}
+
+/// Starts the programs, plays the games and prints the standings
+pub fn run(config: &ArenaConfig) -> Result<(), String>
+{
+    let mut contestants: BTreeMap<u64, Contestant> = Default::default();
+    for program in &config.programs
+    {
+        let contestant = Contestant::spawn(program)?;
+        if contestants.contains_key(&contestant.player.id)
+        {
+            return Err(format!("Username already taken: {}", contestant.player.name));
+        }
+        contestants.insert(contestant.player.id, contestant);
+    }
+    let mut team_wins: BTreeMap<String, u64> = Default::default();
+    if config.teams > 0
+    {
+        let mut players: BTreeMap<u64, Player> = contestants.iter().map(|(id, c)| (*id, c.player.clone())).collect();
+        team::assign(&mut players, config.teams);
+        for (id, player) in players
+        {
+            team_wins.insert(player.team.clone().unwrap(), 0);
+            contestants.get_mut(&id).unwrap().player.team = player.team;
+        }
+    }
+    for game_id in 0..config.games
+    {
+        if contestants.values().filter(|c| c.guest.is_some()).count() < 2
+        {
+            error!("Not enough players left, stopping");
+            break;
+        }
+        let (ended, mut teams) = play(config, &mut contestants, game_id)?;
+        let standings = ended.standings;
+        println!("Game #{} (seed {}): {:?}", ended.game_id, game_seed(config.seed, game_id), ended.reason);
+        teams.sort_by(|a, b| b.points.cmp(&a.points).then(a.name.cmp(&b.name)));
+        for (rank, t) in teams.iter().enumerate()
+        {
+            println!("{:>4}. team {} {} ({} cells)", rank + 1, t.name, t.points, t.cells);
+            if t.points == teams[0].points
+            {
+                *team_wins.entry(t.name.clone()).or_insert(0) += 1;
+            }
+        }
+        for (rank, p) in standings.iter().enumerate()
+        {
+            println!("{:>4}. {} {}", rank + 1, p.name, p.points);
+            let contestant = contestants.get_mut(&p.id).unwrap();
+            contestant.total_points += p.points;
+            let won = match teams.iter().find(|t| t.players.contains(&p.id))
+            {
+                Some(t) => t.points == teams[0].points,
+                None => p.points == standings[0].points,
+            };
+            if won
+            {
+                contestant.wins += 1;
+            }
+        }
+    }
+    if !team_wins.is_empty()
+    {
+        let mut ranked: Vec<(&String, &u64)> = team_wins.iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(a.1));
+        println!("Team wins after {} games", config.games);
+        for (rank, &(name, wins)) in ranked.iter().enumerate()
+        {
+            println!("{:>4}. {} {} wins", rank + 1, name, wins);
+        }
+    }
+    let mut totals: Vec<&Contestant> = contestants.values().collect();
+    totals.sort_by(|a, b| b.wins.cmp(&a.wins).then(b.total_points.cmp(&a.total_points)));
+    println!("Final standings after {} games", config.games);
+    for (rank, c) in totals.iter().enumerate()
+    {
+        println!("{:>4}. {} {} wins, {} points", rank + 1, c.player.name, c.wins, c.total_points);
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests
+{
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    /// Starts `sh` on a script whose first line prints `name`
+    fn contestant(name: &str, script: &str) -> Contestant
+    {
+        let path = env::temp_dir().join(format!("dazzle-arena-{}-{}.sh", process::id(), name));
+        fs::write(&path, format!("echo {}\n{}\n", name, script)).unwrap();
+        let contestant = Contestant::spawn(&format!("sh {}", path.display())).unwrap();
+        fs::remove_file(&path).unwrap();
+        contestant
+    }
+
+    #[test]
+    fn quitting_guests_are_not_asked_for_moves_again()
+    {
+        let config = ArenaConfig {
+            turns: 5,
+            token_rate: 0.0,
+            seed: 42,
+            ms_for_turn: 0,
+            ..Default::default()
+        };
+        let mut contestants: BTreeMap<u64, Contestant> = Default::default();
+        let stayer = contestant("stayer", "yes NOTHING");
+        let quitter = contestant("quitter", "echo QUIT\nexec cat > /dev/null");
+        contestants.insert(stayer.player.id, stayer);
+        contestants.insert(quitter.player.id, quitter);
+        let (ended, _) = play(&config, &mut contestants, 0).unwrap();
+        assert_eq!(ended.standings.len(), 2);
+        let left: Vec<&str> = contestants.values().filter(|c| c.guest.is_none()).map(|c| c.player.name.as_str()).collect();
+        assert_eq!(left, vec!["quitter"]);
+    }
+
+    #[test]
+    fn eliminated_players_are_not_asked_for_moves()
+    {
+        let mut contestants: BTreeMap<u64, Contestant> = Default::default();
+        let stayer = contestant("stayer", "yes NOTHING");
+        let stayer_id = stayer.player.id;
+        // Exits right away, so asking it would fail
+        let eliminated = contestant("eliminated", "exit");
+        let eliminated_id = eliminated.player.id;
+        contestants.insert(stayer_id, stayer);
+        contestants.insert(eliminated_id, eliminated);
+        let players: BTreeMap<u64, Player> = contestants.iter().map(|(id, c)| (*id, c.player.clone())).collect();
+        let mut game = Game::new(players, 0, Default::default()).unwrap();
+        game.tick();
+        game.eliminate(eliminated_id);
+        let overview = game.overview(0);
+        let asked: Vec<u64> = ask_moves(&game, &overview, &mut contestants).into_iter().map(|(id, _)| id).collect();
+        assert_eq!(asked, vec![stayer_id]);
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use serde_json;
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::fs::File;
+
+/// An entry of the registered players file: either the plain `token` or
+/// its hex SHA-1 (as printed by `sha1sum`) in `token_sha1`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegisteredPlayer
+{
+    pub name: String,
+    pub token: Option<String>,
+    pub token_sha1: Option<String>,
+}
+
+pub struct Registry
+{
+    players: HashMap<String, String>,
+}
+
+pub fn token_hash(token: &str) -> String
+{
+    let mut hasher = Sha1::new();
+    hasher.update(token.as_bytes());
+    format!("{}", hasher.digest())
+}
+
+/// Compares in a time that does not depend on where the first difference is
+fn same_bytes(a: &[u8], b: &[u8]) -> bool
+{
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Registry
+{
+    pub fn new(registered: Vec<RegisteredPlayer>) -> Result<Registry, String>
+    {
+        let mut players: HashMap<String, String> = Default::default();
+        for player in registered
+        {
+            let hash = match (player.token, player.token_sha1)
+            {
+                (Some(token), None) => token_hash(&token),
+                (None, Some(hash)) => hash.trim().to_lowercase(),
+                _ => return Err(format!("Player {} needs exactly one of token and token_sha1", player.name)),
+            };
+            if players.insert(player.name.clone(), hash).is_some()
+            {
+                return Err(format!("Player {} is registered twice", player.name));
+            }
+        }
+        Ok(Registry { players })
+    }
+
+    pub fn load(path: &str) -> Result<Registry, String>
+    {
+        let file = File::open(path).map_err(|why| format!("Cannot open {}: {}", path, why))?;
+        let registered: Vec<RegisteredPlayer> = serde_json::from_reader(file).map_err(|why| format!("Invalid players file {}: {}", path, why))?;
+        Registry::new(registered)
+    }
+
+    pub fn verify(&self, name: &str, token: Option<&str>) -> bool
+    {
+        match (self.players.get(name), token)
+        {
+            (Some(hash), Some(token)) => same_bytes(hash.as_bytes(), token_hash(token).as_bytes()),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn registered(name: &str, token: Option<&str>, token_sha1: Option<&str>) -> RegisteredPlayer
+    {
+        RegisteredPlayer {
+            name: String::from(name),
+            token: token.map(String::from),
+            token_sha1: token_sha1.map(String::from),
+        }
+    }
+
+    #[test]
+    fn verifies_tokens_against_the_registry()
+    {
+        let registry = Registry::new(vec![registered("alice", Some("secret"), None)]).unwrap();
+        assert!(registry.verify("alice", Some("secret")));
+        assert!(!registry.verify("alice", Some("guess")));
+        assert!(!registry.verify("alice", None));
+        assert!(!registry.verify("bob", Some("secret")));
+    }
+
+    #[test]
+    fn rejects_duplicate_names()
+    {
+        let players = vec![registered("alice", Some("a"), None), registered("alice", Some("b"), None)];
+        assert_eq!(Registry::new(players).err(), Some(String::from("Player alice is registered twice")));
+    }
+
+    #[test]
+    fn needs_exactly_one_of_token_and_hash()
+    {
+        let both = registered("alice", Some("secret"), Some(&token_hash("secret")));
+        let expected = Some(String::from("Player alice needs exactly one of token and token_sha1"));
+        assert_eq!(Registry::new(vec![both]).err(), expected);
+        assert_eq!(Registry::new(vec![registered("alice", None, None)]).err(), expected);
+    }
+
+    #[test]
+    fn hashes_are_compared_regardless_of_case()
+    {
+        let upper = token_hash("secret").to_uppercase();
+        let registry = Registry::new(vec![registered("alice", None, Some(&upper)), registered("bob", None, Some(&token_hash("pass")))])
+            .unwrap();
+        assert!(registry.verify("alice", Some("secret")));
+        assert!(registry.verify("bob", Some("pass")));
+        assert!(!registry.verify("bob", Some("secret")));
+    }
+
+    #[test]
+    fn loads_the_players_file()
+    {
+        let path = env::temp_dir().join(format!("dazzle-players-{}.json", process::id()));
+        fs::write(&path, r#"[{"name": "alice", "token": "secret"}]"#).unwrap();
+        let registry = Registry::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(registry.unwrap().verify("alice", Some("secret")));
+    }
+}
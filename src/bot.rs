@@ -0,0 +1,268 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use serde::Serialize;
+use serde_json;
+use std::io::ErrorKind;
+use std::thread;
+use std::time::Duration;
+use tungstenite::{Error, Message, WebSocket};
+use tungstenite::client::{AutoStream, connect};
+use url::Url;
+
+pub trait Bot
+{
+    fn name(&self) -> String;
+
+    fn on_turn(&mut self, overview: &Overview) -> ClientCommand;
+
+    fn on_game_starting(&mut self, _ticks_left: u64) {}
+
+    fn on_lobby(&mut self, _status: &LobbyStatus) {}
+
+    fn on_game_end(&mut self, _result: &GameEnded) {}
+
+    fn on_error(&mut self, error: &ServerError)
+    {
+        warn!("Error from server: {}", error);
+    }
+
+    fn done(&self) -> bool
+    {
+        false
+    }
+}
+
+pub struct Runner
+{
+    pub url: Url,
+    pub room: Option<String>,
+    pub token: Option<String>,
+    /// Team to play in, the server picks one when unset and the room has teams
+    pub team: Option<String>,
+    pub max_reconnects: u32,
+    pub reconnect_delay: Duration,
+}
+
+enum SessionEnd
+{
+    Rejected(String),
+    Disconnected(String),
+}
+
+fn send_data<T: ?Sized + Serialize>(ws: &mut WebSocket<AutoStream>, value: &T) -> Result<(), String>
+{
+    let s = serde_json::to_string(value).map_err(|why| format!("{}", why))?;
+    match ws.write_message(Message::text(s))
+    {
+        Ok(_) => Ok(()),
+        Err(Error::Io(ref x)) if x.kind() == ErrorKind::WouldBlock => ws.write_pending().map_err(|why| format!("{}", why)),
+        Err(why) => Err(format!("{}", why)),
+    }
+}
+
+fn read_message(ws: &mut WebSocket<AutoStream>) -> Result<ServerMessage, String>
+{
+    let msg = ws.read_message().map_err(|why| format!("{}", why))?;
+    let text = msg.into_text().map_err(|why| format!("{}", why))?;
+    serde_json::from_str(text.as_str()).map_err(|why| format!("Received garbage: {}", why))
+}
+
+impl Runner
+{
+    pub fn new(url: &str) -> Result<Runner, String>
+    {
+        Ok(Runner {
+               url: Url::parse(url).map_err(|why| format!("Invalid URL {}: {}", url, why))?,
+               room: None,
+               token: None,
+               team: None,
+               max_reconnects: 5,
+               reconnect_delay: Duration::from_secs(1),
+           })
+    }
+
+    pub fn run<B: Bot>(&self, bot: &mut B) -> Result<(), String>
+    {
+        let mut attempts = 0;
+        let mut resume: Option<String> = None;
+        loop
+        {
+            let mut playing = false;
+            match self.session(bot, &mut playing, &mut resume)
+            {
+                Ok(_) => return Ok(()),
+                Err(SessionEnd::Rejected(why)) if attempts == 0 => return Err(why),
+                Err(SessionEnd::Rejected(why)) | Err(SessionEnd::Disconnected(why)) =>
+                {
+                    if playing
+                    {
+                        attempts = 0;
+                    }
+                    attempts += 1;
+                    if attempts > self.max_reconnects
+                    {
+                        return Err(why);
+                    }
+                    warn!("{}, reconnecting ({}/{})", why, attempts, self.max_reconnects);
+                    thread::sleep(self.reconnect_delay);
+                }
+            }
+        }
+    }
+
+    fn session<B: Bot>(&self, bot: &mut B, playing: &mut bool, resume: &mut Option<String>) -> Result<(), SessionEnd>
+    {
+        let mut ws = connect(self.url.clone()).map_err(|why| SessionEnd::Disconnected(format!("Cannot connect to {}: {}", self.url, why)))?;
+        info!("Connected to {}", self.url);
+        let info = PlayerInfo {
+            name: bot.name(),
+            room: self.room.clone(),
+            token: self.token.clone(),
+            session: resume.clone(),
+            team: self.team.clone(),
+        };
+        let handshake = ClientMessage::HandShake(ClientRole::Player(info));
+        send_data(&mut ws, &handshake).map_err(SessionEnd::Disconnected)?;
+        match read_message(&mut ws).map_err(SessionEnd::Disconnected)?
+        {
+            ServerMessage::HandshakeAck(ack) =>
+            {
+                if ack.protocol_version != PROTOCOL_VERSION
+                {
+                    warn!("Server speaks protocol version {}, expected {}", ack.protocol_version, PROTOCOL_VERSION);
+                }
+                *resume = ack.session;
+            }
+            ServerMessage::Error(why) =>
+            {
+                bot.on_error(&why);
+                return Err(SessionEnd::Rejected(format!("{}", why)));
+            }
+            _ => return Err(SessionEnd::Disconnected(String::from("Handshake not acknowledged"))),
+        }
+        loop
+        {
+            match read_message(&mut ws).map_err(SessionEnd::Disconnected)?
+            {
+                ServerMessage::CommandResult(ServerResponse::Ok) |
+                ServerMessage::HandshakeAck(_) => (),
+                ServerMessage::CommandResult(ServerResponse::Error(why)) |
+                ServerMessage::Error(why) => bot.on_error(&why),
+                ServerMessage::GameStarting { ticks_left } => bot.on_game_starting(ticks_left),
+                ServerMessage::Lobby(status) => bot.on_lobby(&status),
+                ServerMessage::GameEnded(result) => bot.on_game_end(&result),
+                ServerMessage::Overview(overview) =>
+                {
+                    *playing = true;
+                    if overview.turns_left == 0
+                    {
+                        continue;
+                    }
+                    let command = bot.on_turn(&overview);
+                    if !bot.done()
+                    {
+                        let command = TurnCommand {
+                            tick: overview.tick,
+                            command,
+                        };
+                        send_data(&mut ws, &ClientMessage::Command(command)).map_err(SessionEnd::Disconnected)?;
+                    }
+                }
+            }
+            if bot.done()
+            {
+                let _ = ws.close(None);
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::net::TcpListener;
+    use tungstenite::accept;
+
+    /// Leaves as soon as it gets to a lobby
+    struct Quitter
+    {
+        lobbies: u32,
+    }
+
+    impl Bot for Quitter
+    {
+        fn name(&self) -> String
+        {
+            String::from("quitter")
+        }
+
+        fn on_turn(&mut self, _overview: &Overview) -> ClientCommand
+        {
+            ClientCommand::Nothing
+        }
+
+        fn on_lobby(&mut self, _status: &LobbyStatus)
+        {
+            self.lobbies += 1;
+        }
+
+        fn done(&self) -> bool
+        {
+            self.lobbies > 0
+        }
+    }
+
+    #[test]
+    fn runner_resumes_its_session_after_reconnecting()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut sessions: Vec<Option<String>> = Vec::new();
+            for connection in 0..2
+            {
+                let mut ws = accept(listener.accept().unwrap().0).unwrap();
+                let text = ws.read_message().unwrap().into_text().unwrap();
+                match serde_json::from_str(&text).unwrap()
+                {
+                    ClientMessage::HandShake(ClientRole::Player(info)) => sessions.push(info.session),
+                    _ => panic!("not a player handshake"),
+                }
+                let ack = ServerMessage::HandshakeAck(HandshakeAck {
+                                                          protocol_version: PROTOCOL_VERSION,
+                                                          player_id: Some(1),
+                                                          session: Some(String::from("resume-me")),
+                                                      });
+                ws.write_message(Message::text(serde_json::to_string(&ack).unwrap())).unwrap();
+                if connection == 1
+                {
+                    let lobby = ServerMessage::Lobby(LobbyStatus {
+                                                         room: String::from("default"),
+                                                         queued: Vec::new(),
+                                                         ticks_left: None,
+                                                         ms_left: None,
+                                                         min_players: 2,
+                                                         max_players: None,
+                                                     });
+                    ws.write_message(Message::text(serde_json::to_string(&lobby).unwrap())).unwrap();
+                    let _ = ws.read_message();
+                }
+            }
+            sessions
+        });
+        let mut runner = Runner::new(&url).unwrap();
+        runner.reconnect_delay = Duration::from_millis(0);
+        runner.run(&mut Quitter { lobbies: 0 }).unwrap();
+        assert_eq!(server.join().unwrap(), vec![None, Some(String::from("resume-me"))]);
+    }
+}
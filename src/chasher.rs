@@ -46,6 +46,14 @@ impl CHasher
 pub fn player_hash(player_info: &PlayerInfo) -> u64
 {
     let mut hasher = CHasher::new();
-    hasher.update(player_info.name.as_str().as_bytes());
+    hasher.update(player_info.name.as_bytes());
+    hasher.finalize()
+}
+
+pub fn game_seed(seed: u64, game_id: u64) -> u64
+{
+    let mut hasher = CHasher::new();
+    hasher.update(&seed.to_le_bytes());
+    hasher.update(&game_id.to_le_bytes());
     hasher.finalize()
 }
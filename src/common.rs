@@ -7,47 +7,183 @@
  *
  */
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+use std::fmt;
+
+pub const PROTOCOL_VERSION: u32 = 11;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Point
 {
     pub x: usize,
     pub y: usize,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Player
 {
     pub name: String,
     pub points: u64,
     pub position: Point,
     pub id: u64,
+    pub active: bool,
+    /// Cells walked outside of the own territory, only used by the elimination rules
+    #[serde(default)]
+    pub trail: Vec<Point>,
+    #[serde(default = "default_alive")]
+    pub alive: bool,
+    pub team: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Player
+{
+    /// A player with no points, standing at the origin until the game places it
+    pub fn new(id: u64, name: String) -> Player
+    {
+        Player {
+            name,
+            points: 0,
+            position: Point { x: 0, y: 0 },
+            id,
+            active: true,
+            trail: Vec::new(),
+            alive: true,
+            team: None,
+        }
+    }
+}
+
+fn default_alive() -> bool
+{
+    true
+}
+
+/// A token pays `value` points per cashed cell and disappears after `ttl` more ticks
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Token
+{
+    pub position: Point,
+    pub value: u64,
+    pub ttl: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowerUpKind
+{
+    /// Every move is made twice in the same tick
+    Speed,
+    /// The territory cannot be painted over, stolen or bombed
+    Shield,
+    /// Clears the opponents' cells around the player as soon as it is taken
+    Bomb,
+}
+
+/// A power-up lying on the board, taken by stepping on it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerUp
+{
+    pub position: Point,
+    pub kind: PowerUpKind,
+    pub ttl: Option<u64>,
+}
+
+/// A power-up acting on a player for `ticks_left` more ticks
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Effect
+{
+    pub player_id: u64,
+    pub kind: PowerUpKind,
+    pub ticks_left: u64,
+}
+
+/// `color` is the value the cells of the team have in the grid
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TeamScore
+{
+    pub name: String,
+    pub color: u64,
+    pub players: Vec<u64>,
+    pub points: u64,
+    pub cells: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Overview
 {
     pub players: Vec<Player>,
     pub grid: Vec<Vec<Option<u64>>>,
+    pub walls: Vec<Point>,
     pub turns_left: u64,
     pub ms_for_turn: u64,
-    pub tokens: Vec<Point>,
+    pub tokens: Vec<Token>,
+    pub power_ups: Vec<PowerUp>,
+    pub effects: Vec<Effect>,
+    pub teams: Vec<TeamScore>,
     pub game_id: u64,
+    pub seed: u64,
+    pub tick: u64,
+    pub deadline_ms: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PlayerInfo
 {
     pub name: String,
+    pub room: Option<String>,
+    pub token: Option<String>,
+    pub session: Option<String>,
+    pub team: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ViewerInfo
+{
+    pub room: Option<String>,
+}
+
+/// A bare `"Viewer"`, as sent before the rooms, is still accepted and watches every room
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(from = "WireRole")]
 pub enum ClientRole
+{
+    Viewer(ViewerInfo),
+    Player(PlayerInfo),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WireRole
+{
+    Bare(BareRole),
+    Tagged(TaggedRole),
+}
+
+#[derive(Deserialize)]
+enum BareRole
 {
     Viewer,
+}
+
+#[derive(Deserialize)]
+enum TaggedRole
+{
+    Viewer(ViewerInfo),
     Player(PlayerInfo),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl From<WireRole> for ClientRole
+{
+    fn from(role: WireRole) -> ClientRole
+    {
+        match role
+        {
+            WireRole::Bare(BareRole::Viewer) => ClientRole::Viewer(Default::default()),
+            WireRole::Tagged(TaggedRole::Viewer(info)) => ClientRole::Viewer(info),
+            WireRole::Tagged(TaggedRole::Player(info)) => ClientRole::Player(info),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Direction
 {
     Up,
@@ -56,23 +192,267 @@ pub enum Direction
     Right,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum ClientCommand
 {
     Move(Direction),
     Nothing,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GameEndReason
+{
+    TurnsExhausted,
+    NoPlayersLeft,
+    LastPlayerStanding,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Standing
+{
+    pub id: u64,
+    pub name: String,
+    pub points: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameEnded
+{
+    pub game_id: u64,
+    pub standings: Vec<Standing>,
+    pub reason: GameEndReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode
+{
+    InvalidMessage,
+    NameTaken,
+    InvalidName,
+    AuthFailed,
+    UnknownRoom,
+    NotAllowed,
+    NoActiveGame,
+    AlreadyMoved,
+    StaleTick,
+    InvalidMove,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerError
+{
+    pub code: ErrorCode,
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerResponse
 {
     Ok,
-    Error(String),
+    Error(ServerError),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueuedPlayer
+{
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LobbyStatus
+{
+    pub room: String,
+    pub queued: Vec<QueuedPlayer>,
+    pub ticks_left: Option<u64>,
+    pub ms_left: Option<u64>,
+    pub min_players: u64,
+    pub max_players: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HandshakeAck
+{
+    pub protocol_version: u32,
+    pub player_id: Option<u64>,
+    pub session: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ServerMessage
+{
+    HandshakeAck(HandshakeAck),
+    Overview(Overview),
+    CommandResult(ServerResponse),
+    GameStarting { ticks_left: u64 },
+    Lobby(LobbyStatus),
+    GameEnded(GameEnded),
+    Error(ServerError),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TurnCommand
+{
+    pub tick: u64,
+    pub command: ClientCommand,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessage
 {
     HandShake(ClientRole),
-    Command(ClientCommand),
+    Command(TurnCommand),
+}
+
+impl ServerError
+{
+    pub fn new(code: ErrorCode, message: &str) -> ServerError
+    {
+        ServerError {
+            code,
+            message: String::from(message),
+        }
+    }
+}
+
+impl fmt::Display for ServerError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "{} ({:?})", self.message, self.code)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use game::Game;
+    use serde_json;
+    use std::collections::BTreeMap;
+
+    fn handshake(json: &str) -> ClientRole
+    {
+        match serde_json::from_str(json).unwrap()
+        {
+            ClientMessage::HandShake(role) => role,
+            _ => panic!("not a handshake"),
+        }
+    }
+
+    /// Serializes `message`, reads it back and checks that it serializes the same way again
+    fn round_trip(message: ServerMessage) -> ServerMessage
+    {
+        let json = serde_json::to_string(&message).unwrap();
+        let parsed: ServerMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        parsed
+    }
+
+    #[test]
+    fn server_messages_survive_a_round_trip()
+    {
+        let ack = HandshakeAck {
+            protocol_version: PROTOCOL_VERSION,
+            player_id: Some(7),
+            session: Some(String::from("abc")),
+        };
+        match round_trip(ServerMessage::HandshakeAck(ack))
+        {
+            ServerMessage::HandshakeAck(ack) => assert_eq!((ack.protocol_version, ack.player_id), (PROTOCOL_VERSION, Some(7))),
+            other => panic!("not a handshake ack: {:?}", other),
+        }
+        let players: BTreeMap<u64, Player> = (1..3).map(|id| (id, Player::new(id, format!("p{}", id)))).collect();
+        let mut game = Game::new(players, 3, Default::default()).unwrap();
+        game.tick();
+        match round_trip(ServerMessage::Overview(game.overview(100)))
+        {
+            ServerMessage::Overview(parsed) => assert_eq!(parsed, game.overview(100)),
+            other => panic!("not an overview: {:?}", other),
+        }
+        match round_trip(ServerMessage::CommandResult(ServerResponse::Ok))
+        {
+            ServerMessage::CommandResult(ServerResponse::Ok) => (),
+            other => panic!("not an ok: {:?}", other),
+        }
+        let error = ServerError::new(ErrorCode::StaleTick, "too late");
+        match round_trip(ServerMessage::CommandResult(ServerResponse::Error(error.clone())))
+        {
+            ServerMessage::CommandResult(ServerResponse::Error(parsed)) => assert_eq!(parsed, error),
+            other => panic!("not a command error: {:?}", other),
+        }
+        match round_trip(ServerMessage::GameStarting { ticks_left: 4 })
+        {
+            ServerMessage::GameStarting { ticks_left } => assert_eq!(ticks_left, 4),
+            other => panic!("not a countdown: {:?}", other),
+        }
+        let ended = GameEnded {
+            game_id: 3,
+            standings: vec![Standing {
+                                id: 1,
+                                name: String::from("p1"),
+                                points: 10,
+                            }],
+            reason: GameEndReason::TurnsExhausted,
+        };
+        match round_trip(ServerMessage::GameEnded(ended.clone()))
+        {
+            ServerMessage::GameEnded(parsed) => assert_eq!(parsed, ended),
+            other => panic!("not a game end: {:?}", other),
+        }
+        match round_trip(ServerMessage::Error(error.clone()))
+        {
+            ServerMessage::Error(parsed) => assert_eq!(parsed, error),
+            other => panic!("not an error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lobby_status_survives_a_round_trip()
+    {
+        let status = LobbyStatus {
+            room: String::from("duel"),
+            queued: vec![QueuedPlayer {
+                             id: 1,
+                             name: String::from("p1"),
+                         }],
+            ticks_left: Some(3),
+            ms_left: Some(1500),
+            min_players: 2,
+            max_players: None,
+        };
+        match round_trip(ServerMessage::Lobby(status.clone()))
+        {
+            ServerMessage::Lobby(parsed) => assert_eq!(parsed, status),
+            other => panic!("not a lobby status: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bare_viewer_handshake_is_still_accepted()
+    {
+        match handshake(r#"{"HandShake":"Viewer"}"#)
+        {
+            ClientRole::Viewer(info) => assert_eq!(info.room, None),
+            _ => panic!("not a viewer"),
+        }
+        match handshake(r#"{"HandShake":{"Viewer":{"room":"duel"}}}"#)
+        {
+            ClientRole::Viewer(info) => assert_eq!(info.room, Some(String::from("duel"))),
+            _ => panic!("not a viewer"),
+        }
+        let info = PlayerInfo {
+            name: String::from("a"),
+            room: None,
+            token: None,
+            session: None,
+            team: None,
+        };
+        let player = serde_json::to_string(&ClientMessage::HandShake(ClientRole::Player(info))).unwrap();
+        match handshake(&player)
+        {
+            ClientRole::Player(info) => assert_eq!(info.name, "a"),
+            _ => panic!("not a player"),
+        }
+    }
 }
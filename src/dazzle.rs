@@ -7,171 +7,121 @@
  *
  */
 
+extern crate dazzle;
 #[macro_use]
 extern crate log;
 extern crate mowl;
-#[macro_use]
-extern crate serde_derive;
-extern crate serde_json;
-extern crate twox_hash;
-extern crate tungstenite;
-extern crate serde;
-extern crate rand;
-extern crate url;
-
-mod common;
 
-use common::*;
-use serde::Serialize;
+use dazzle::{Bot, Runner};
+use dazzle::common::*;
+use dazzle::guest::Guest;
 use std::env;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::io::ErrorKind;
-use std::process::{Command, Stdio};
-use tungstenite::{Error, Message, WebSocket};
-use tungstenite::client::connect;
-use url::Url;
 
-fn send_data<T: ?Sized + Serialize, U: Read + Write>(ws: &mut WebSocket<U>, value: &T)
+struct GuestBot
+{
+    guest: Guest,
+    name: String,
+    done: bool,
+}
+
+impl GuestBot
 {
-    match serde_json::to_string(value)
+    fn exchange(&mut self, overview: &Overview) -> ClientCommand
     {
-        Ok(s) =>
+        if let Err(why) = self.guest.send_overview(overview)
+        {
+            error!("Cannot talk to guest program: {}", why);
+            self.done = true;
+            return ClientCommand::Nothing;
+        }
+        match self.guest.read_command()
         {
-            match ws.write_message(Message::text(s))
+            Ok(Some(command)) => command,
+            Ok(None) =>
+            {
+                self.done = true;
+                ClientCommand::Nothing
+            }
+            Err(why) =>
             {
-                Ok(_) =>
-                {}
-                Err(Error::Io(x)) =>
-                {
-                    match x.kind()
-                    {
-                        ErrorKind::WouldBlock =>
-                        {
-                            match ws.write_pending()
-                            {
-                                Ok(_) =>
-                                {}
-                                Err(why) => error!("Unable to flush WebSocket {}", why),
-                            }
-                        }
-                        _ => error!("I/O error"),
-                    }
-                }
-                _ => panic!("Connection closed!"),
+                error!("{}", why);
+                self.done = true;
+                ClientCommand::Nothing
             }
         }
-        Err(_) => unreachable!(),
-    };
+    }
+}
+
+impl Bot for GuestBot
+{
+    fn name(&self) -> String
+    {
+        self.name.clone()
+    }
+
+    fn on_turn(&mut self, overview: &Overview) -> ClientCommand
+    {
+        self.exchange(overview)
+    }
+
+    fn on_game_end(&mut self, result: &GameEnded)
+    {
+        if let Err(why) = self.guest.send_game_ended(result)
+        {
+            error!("Cannot talk to guest program: {}", why);
+            self.done = true;
+        }
+    }
+
+    fn done(&self) -> bool
+    {
+        self.done
+    }
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(many_single_char_names, needless_range_loop))]
 fn main()
 {
     mowl::init_with_level(log::LogLevel::Info).unwrap();
     info!("Starting dazzle...");
-    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut room: Option<String> = None;
+    let mut token: Option<String> = env::var("DAZZLE_TOKEN").ok();
+    let mut team: Option<String> = None;
+    while args.len() >= 2 && (args[0] == "--room" || args[0] == "--token" || args[0] == "--team")
+    {
+        match args[0].as_str()
+        {
+            "--room" => room = Some(args[1].clone()),
+            "--token" => token = Some(args[1].clone()),
+            _ => team = Some(args[1].clone()),
+        }
+        args.drain(..2);
+    }
     if args.len() < 2
     {
-        error!("Usage: dazzle ws://ip:port/ <program> [arguments]");
+        error!("Usage: dazzle [--room NAME] [--token TOKEN] [--team NAME] ws://ip:port/ <program> [arguments]");
+        error!("The token can also be given through the DAZZLE_TOKEN environment variable");
         return;
     }
-    info!("dazzle started successfully!");
-    let mut websocket = match connect(Url::parse(args[0].as_str()).unwrap())
+    let mut runner = match Runner::new(args[0].as_str())
     {
-        Ok(ws) => ws,
-        Err(why) => panic!("Cannot connect to {} -> {}", args[0], why),
+        Ok(r) => r,
+        Err(why) => panic!("{}", why),
     };
-    info!("Connected to {}", args[0]);
-    let child = Command::new(&args[1])
-        .args(args.iter().skip(2))
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Cannot fork");
+    runner.room = room;
+    runner.token = token;
+    runner.team = team;
+    info!("dazzle started successfully!");
+    let mut guest = Guest::spawn(&args[1], &args[2..]).expect("Cannot fork");
     info!("Started guest program");
-    let mut stdout = BufReader::new(child.stdout.unwrap());
-    let mut stdin = child.stdin.unwrap();
-    let mut name_string = String::new();
-    stdout.read_line(&mut name_string).unwrap();
-    let connect_message = ClientMessage::HandShake(ClientRole::Player(PlayerInfo { name: name_string }));
-    send_data(&mut websocket, &connect_message);
-    let response: ServerResponse = serde_json::from_str(websocket.read_message().unwrap().to_text().unwrap()).unwrap();
-    if let ServerResponse::Error(s) = response
+    let name = guest.read_name().unwrap();
+    let mut bot = GuestBot {
+        guest,
+        name,
+        done: false,
+    };
+    if let Err(why) = runner.run(&mut bot)
     {
-        panic!("Error from server: {}", s);
+        error!("{}", why);
     }
-    loop
-    {
-        let overview: Overview = serde_json::from_str(websocket.read_message().unwrap().to_text().unwrap()).unwrap();
-        let n = overview.players.len();
-        let r = overview.grid.len();
-        let c = overview.grid[0].len();
-        let t = overview.tokens.len();
-        let e = overview.turns_left;
-        let m = overview.ms_for_turn;
-        writeln!(&mut stdin, "{} {} {} {} {} {}", n, r, c, t, e, m).unwrap();
-        for p in &overview.players
-        {
-            writeln!(&mut stdin, "{} {} {} {} {}", p.id, p.name, p.points, p.position.x, p.position.y).unwrap();
-        }
-        for rows in &overview.grid
-        {
-            for pos in 0..rows.len()
-            {
-                if let Some(value) = rows[pos]
-                {
-                    write!(&mut stdin, "{}", value).unwrap();
-                }
-                else
-                {
-                    write!(&mut stdin, "{}", -1).unwrap();
-                }
-                if pos != rows.len() - 1
-                {
-                    write!(&mut stdin, " ").unwrap();
-                }
-            }
-            writeln!(&mut stdin, "").unwrap();
-        }
-        for token in &overview.tokens
-        {
-            writeln!(&mut stdin, "{} {}", token.x, token.y).unwrap();
-        }
-        stdin.flush().unwrap();
-        let mut cli = String::new();
-        stdout.read_line(&mut cli).unwrap();
-        let command = match cli.trim()
-        {
-            "NOTHING" => ClientCommand::Nothing,
-            "UP" => ClientCommand::Move(Direction::Up),
-            "DOWN" => ClientCommand::Move(Direction::Down),
-            "LEFT" => ClientCommand::Move(Direction::Left),
-            "RIGHT" => ClientCommand::Move(Direction::Right),
-            "QUIT" => break,
-            cmd => panic!("Invalid command: {}", cmd),
-        };
-        let command = ClientMessage::Command(command);
-        send_data(&mut websocket, &command);
-        let _: ServerResponse = serde_json::from_str(websocket.read_message().unwrap().to_text().unwrap()).unwrap();
-    }
-    websocket.close(None).unwrap();
 }
-
-/* INPUT FORMAT
-N: number of players
-R, C: rows and columns of the grid
-T: number of tokens
-E: turns left
-M: milliseconds to make the move
-positionals arguments
-ID: player id
-S: player name (string)
-P: points
-X, Y: position
-
-INPUT:
-N R C T E M
-N lines containing: ID S P X Y
-R lines, each containing C numbers: -1 for nothing, the player id otherwise
-T lines: X Y
-*/
@@ -0,0 +1,133 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+extern crate dazzle;
+#[macro_use]
+extern crate log;
+extern crate mowl;
+extern crate rand;
+
+use dazzle::arena::{self, ArenaConfig};
+use dazzle::common::PowerUpKind;
+use dazzle::game::TokenPolicy;
+use dazzle::map::Map;
+use dazzle::rules;
+use dazzle::spawn::Symmetry;
+use std::env;
+use std::process;
+
+fn usage() -> !
+{
+    error!("Usage: dazzle-arena [--games N] [--turns N] [--seed N] [--token-rate R] [--tick-ms N] [--replays DIR] \
+            [--rules NAME] [--rows N] [--cols N] [--obstacles D] \
+            [--spawn-distance N] [--symmetry none|mirror|rotational] \
+            [--token-policy uniform|away-from-players|center-weighted] [--token-ttl N] [--max-tokens N] \
+            [--token-values V,V,...] [--power-ups RATE] [--power-up-kinds speed,shield,bomb] [--teams N] [--map FILE] [--maps DIR] <program> <program> [program...]");
+    process::exit(1);
+}
+
+fn parse_args() -> ArenaConfig
+{
+    let mut config = ArenaConfig {
+        seed: rand::random(),
+        ..Default::default()
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next()
+    {
+        if !arg.starts_with("--")
+        {
+            config.programs.push(arg);
+            continue;
+        }
+        let value = match args.next()
+        {
+            Some(v) => v,
+            None => usage(),
+        };
+        let ok = match arg.as_str()
+        {
+            "--games" => value.parse().map(|v| config.games = v).is_ok(),
+            "--turns" => value.parse().map(|v| config.turns = v).is_ok(),
+            "--seed" => value.parse().map(|v| config.seed = v).is_ok(),
+            "--token-rate" => value.parse().map(|v| config.token_rate = v).is_ok(),
+            "--tick-ms" => value.parse().map(|v| config.ms_for_turn = v).is_ok(),
+            "--replays" =>
+            {
+                config.replay_dir = Some(value);
+                true
+            }
+            "--rows" => value.parse().map(|v| config.board.rows = Some(v)).is_ok(),
+            "--cols" => value.parse().map(|v| config.board.cols = Some(v)).is_ok(),
+            "--obstacles" => value.parse().map(|v| config.board.obstacle_density = v).is_ok(),
+            "--spawn-distance" => value.parse().map(|v| config.board.spawn_distance = v).is_ok(),
+            "--symmetry" => Symmetry::by_name(&value).map(|v| config.board.symmetry = v).is_some(),
+            "--token-policy" => TokenPolicy::by_name(&value).map(|v| config.tokens.policy = v).is_some(),
+            "--token-ttl" => value.parse().map(|v| config.tokens.ttl = Some(v)).is_ok(),
+            "--max-tokens" => value.parse().map(|v| config.tokens.max_tokens = Some(v)).is_ok(),
+            "--teams" => value.parse().map(|v| config.teams = v).is_ok(),
+            "--power-ups" => value.parse().map(|v| config.power_ups.rate = v).is_ok(),
+            "--power-up-kinds" =>
+            {
+                value.split(',')
+                    .map(|v| PowerUpKind::by_name(v.trim()))
+                    .collect::<Option<Vec<PowerUpKind>>>()
+                    .map(|v| config.power_ups.kinds = v)
+                    .is_some()
+            }
+            "--token-values" =>
+            {
+                value.split(',')
+                    .map(|v| v.trim().parse())
+                    .collect::<Result<Vec<u64>, _>>()
+                    .map(|v| config.tokens.values = v)
+                    .is_ok()
+            }
+            "--map" =>
+            {
+                Map::load(&value)
+                    .map(|map| config.maps.push(map))
+                    .map_err(|why| error!("{}", why))
+                    .is_ok()
+            }
+            "--maps" =>
+            {
+                Map::load_dir(&value)
+                    .map(|maps| config.maps.extend(maps))
+                    .map_err(|why| error!("{}", why))
+                    .is_ok()
+            }
+            "--rules" =>
+            {
+                config.rules = value;
+                rules::by_name(&config.rules).is_some()
+            }
+            _ => false,
+        };
+        if !ok
+        {
+            usage();
+        }
+    }
+    if config.programs.len() < 2
+    {
+        usage();
+    }
+    config
+}
+
+fn main()
+{
+    mowl::init_with_level(log::LogLevel::Warn).unwrap();
+    if let Err(why) = arena::run(&parse_args())
+    {
+        error!("{}", why);
+        process::exit(1);
+    }
+}
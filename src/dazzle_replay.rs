@@ -0,0 +1,143 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+extern crate dazzle;
+#[macro_use]
+extern crate log;
+extern crate mowl;
+
+use dazzle::common::*;
+use dazzle::replay::Replay;
+use std::env;
+use std::io::{BufRead, Write, stdin, stdout};
+
+fn cell_symbol(overview: &Overview, x: usize, y: usize) -> char
+{
+    let letters: Vec<char> = "abcdefghijklmnopqrstuvwxyz".chars().collect();
+    let index = |id: u64| overview.players.iter().position(|p| p.id == id);
+    if let Some(i) = overview.players.iter().position(|p| p.position.x == x && p.position.y == y)
+    {
+        return letters[i % letters.len()].to_ascii_uppercase();
+    }
+    if overview.walls.iter().any(|w| w.x == x && w.y == y)
+    {
+        return '#';
+    }
+    if overview.players.iter().any(|p| p.trail.iter().any(|t| t.x == x && t.y == y))
+    {
+        return '+';
+    }
+    if overview.tokens.iter().any(|t| t.position.x == x && t.position.y == y)
+    {
+        return '*';
+    }
+    if let Some(power_up) = overview.power_ups.iter().find(|u| u.position.x == x && u.position.y == y)
+    {
+        return match power_up.kind
+        {
+            PowerUpKind::Speed => '>',
+            PowerUpKind::Shield => '@',
+            PowerUpKind::Bomb => '!',
+        };
+    }
+    match overview.grid[y][x].and_then(index)
+    {
+        Some(i) => letters[i % letters.len()],
+        None => '.',
+    }
+}
+
+fn show_frame(replay: &Replay, n: usize)
+{
+    let overview = &replay.frames[n].overview;
+    println!("Game #{} (seed {}) - frame {}/{} - {} turns left",
+             replay.game_id,
+             replay.config.seed,
+             n,
+             replay.frames.len() - 1,
+             overview.turns_left);
+    for y in 0..overview.grid.len()
+    {
+        let row: String = (0..overview.grid[y].len()).map(|x| cell_symbol(overview, x, y)).collect();
+        println!("{}", row);
+    }
+    for (i, p) in overview.players.iter().enumerate()
+    {
+        println!("{} {} ({}): {} points at ({}, {}){}",
+                 ((b'A' + (i % 26) as u8) as char),
+                 p.name,
+                 p.id,
+                 p.points,
+                 p.position.x,
+                 p.position.y,
+                 if p.alive { "" } else { " - eliminated" });
+    }
+    for t in &overview.teams
+    {
+        println!("team {}: {} points, {} cells", t.name, t.points, t.cells);
+    }
+    for event in &replay.frames[n].events
+    {
+        println!("> {:?}", event);
+    }
+}
+
+fn main()
+{
+    mowl::init_with_level(log::LogLevel::Info).unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty()
+    {
+        error!("Usage: dazzle-replay <replay.json> [--verify]");
+        return;
+    }
+    let replay = match Replay::load(&args[0])
+    {
+        Ok(r) => r,
+        Err(why) => panic!("{}", why),
+    };
+    match replay.verify()
+    {
+        Ok(_) => info!("Replay verified: {} frames", replay.frames.len()),
+        Err(why) => error!("Replay verification failed: {}", why),
+    }
+    if args.iter().any(|a| a == "--verify") || replay.frames.is_empty()
+    {
+        return;
+    }
+    let mut current = 0;
+    let input = stdin();
+    loop
+    {
+        show_frame(&replay, current);
+        print!("[n]ext, [p]revious, [g]oto N, [q]uit: ");
+        stdout().flush().unwrap();
+        let mut line = String::new();
+        if input.lock().read_line(&mut line).unwrap() == 0
+        {
+            break;
+        }
+        let mut words = line.split_whitespace();
+        match words.next()
+        {
+            None | Some("n") => current = (current + 1).min(replay.frames.len() - 1),
+            Some("p") => current = current.saturating_sub(1),
+            Some("g") =>
+            {
+                match words.next().and_then(|w| w.parse::<usize>().ok())
+                {
+                    Some(n) => current = n.min(replay.frames.len() - 1),
+                    None => println!("Invalid frame number"),
+                }
+            }
+            Some("q") => break,
+            Some(cmd) => println!("Unknown command: {}", cmd),
+        }
+    }
+}
@@ -7,28 +7,18 @@
  *
  */
 
+extern crate dazzle;
 #[macro_use]
 extern crate log;
 extern crate mowl;
-#[macro_use]
-extern crate serde_derive;
 extern crate serde_json;
-extern crate twox_hash;
-extern crate tungstenite;
-extern crate serde;
-extern crate rand;
-
-mod chasher;
-mod common;
-mod server;
-mod game;
 
-use server::Server;
-use server::ServerConfig;
+use dazzle::{Server, ServerConfig};
 use std::env;
 use std::fs::File;
+use std::path::Path;
 
-fn try_open_config(custom: Option<String>) -> Option<File>
+fn try_open_config(custom: Option<String>) -> Option<(String, File)>
 {
     let mut file_list = vec![String::from("dazzled.json"), String::from("/etc/dazzled.json")];
     if let Some(p) = custom
@@ -37,10 +27,9 @@ fn try_open_config(custom: Option<String>) -> Option<File>
     }
     for file_name in &file_list
     {
-        let f = File::open(file_name);
-        if f.is_ok()
+        if let Ok(f) = File::open(file_name)
         {
-            return f.ok();
+            return Some((file_name.clone(), f));
         }
     }
     None
@@ -53,18 +42,20 @@ fn main()
     let args: Vec<String> = env::args().collect();
     let custom_path = match args.len()
     {
-        0...1 => None,
+        0..=1 => None,
         _ => Some(args[2].clone()),
     };
     let config = match try_open_config(custom_path)
     {
-        Some(f) =>
+        Some((path, f)) =>
         {
-            match serde_json::from_reader(f)
+            let mut config: ServerConfig = match serde_json::from_reader(f)
             {
                 Ok(x) => x,
                 Err(why) => panic!("Invalid JSON file: {}", why),
-            }
+            };
+            config.resolve_paths(Path::new(&path).parent().unwrap_or_else(|| Path::new("")));
+            config
         }
         None => ServerConfig::new(),
     };
@@ -8,248 +8,1191 @@
  */
 
 use common::*;
-use rand::{Rng, StdRng};
+use grid::Grid;
+use rand::{Isaac64Rng, Rng, SeedableRng};
 use rand::distributions::{IndependentSample, Range};
-use std::collections::{HashMap, HashSet, VecDeque};
+use powerup::{self, PowerUpConfig};
+use rules::{self, DEFAULT_RULES, Rules};
+use spawn::{self, Symmetry};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use team;
+
+/// What happens to a player that left the game for good
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum GhostPolicy
+{
+    /// The player stays on the board as an obstacle and keeps its territory
+    #[default]
+    Freeze,
+    /// The player's territory is cleared and its cell can be walked on
+    RemoveTerritory,
+}
+
+/// Size of the board and its walls. Unless fixed, the board grows with the players:
+/// `rows = base_rows + players / players_per_row`, `cols = rows * cols_per_row + extra_cols`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct BoardConfig
+{
+    pub rows: Option<usize>,
+    pub cols: Option<usize>,
+    pub base_rows: usize,
+    pub players_per_row: usize,
+    pub cols_per_row: usize,
+    pub extra_cols: usize,
+    /// Chance of each cell to become a wall, drawn from the game seed
+    pub obstacle_density: f64,
+    pub walls: Vec<Point>,
+    /// Starting cells, handed out in a random order; players beyond them start anywhere
+    pub spawns: Vec<Point>,
+    /// Fewest steps between two players placed anywhere, relaxed when the board is too crowded
+    pub spawn_distance: usize,
+    /// Places the players anywhere in pairs of symmetric cells; procedural walls follow it too
+    pub symmetry: Symmetry,
+    /// Tokens already on the board when the game starts
+    pub tokens: Vec<Point>,
+    /// When not empty, new tokens only appear on these cells
+    pub token_zones: Vec<Point>,
+}
+
+impl Default for BoardConfig
+{
+    fn default() -> BoardConfig
+    {
+        BoardConfig {
+            rows: None,
+            cols: None,
+            base_rows: 8,
+            players_per_row: 2,
+            cols_per_row: 2,
+            extra_cols: 1,
+            obstacle_density: 0.0,
+            walls: Vec::new(),
+            spawns: Vec::new(),
+            spawn_distance: 3,
+            symmetry: Symmetry::None,
+            tokens: Vec::new(),
+            token_zones: Vec::new(),
+        }
+    }
+}
+
+/// Where new tokens appear, always on a free cell (or token zone) without a player
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TokenPolicy
+{
+    #[default]
+    Uniform,
+    /// Never closer than `player_distance` steps to an active player
+    AwayFromPlayers,
+    /// Cells near the center of the board are more likely
+    CenterWeighted,
+}
+
+impl TokenPolicy
+{
+    pub fn by_name(name: &str) -> Option<TokenPolicy>
+    {
+        match name
+        {
+            "uniform" => Some(TokenPolicy::Uniform),
+            "away-from-players" => Some(TokenPolicy::AwayFromPlayers),
+            "center-weighted" => Some(TokenPolicy::CenterWeighted),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TokenConfig
+{
+    pub policy: TokenPolicy,
+    pub player_distance: usize,
+    /// No token spawns while this many are on the board
+    pub max_tokens: Option<usize>,
+    /// Ticks a new token stays on the board, forever if unset
+    pub ttl: Option<u64>,
+    /// Multipliers of the points paid by a token, each new token draws one of them
+    pub values: Vec<u64>,
+}
+
+impl Default for TokenConfig
+{
+    fn default() -> TokenConfig
+    {
+        TokenConfig {
+            policy: TokenPolicy::Uniform,
+            player_distance: 3,
+            max_tokens: None,
+            ttl: None,
+            values: vec![1],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameConfig
+{
+    pub turns: u64,
+    pub token_rate: f64,
+    pub seed: u64,
+    #[serde(default)]
+    pub ghost_policy: GhostPolicy,
+    #[serde(default = "default_rules")]
+    pub rules: String,
+    #[serde(default)]
+    pub board: BoardConfig,
+    #[serde(default)]
+    pub tokens: TokenConfig,
+    #[serde(default)]
+    pub power_ups: PowerUpConfig,
+}
+
+fn default_rules() -> String
+{
+    String::from(DEFAULT_RULES)
+}
+
+impl Default for GameConfig
+{
+    fn default() -> GameConfig
+    {
+        GameConfig {
+            turns: 300,
+            token_rate: 2.5,
+            seed: 0,
+            ghost_policy: Default::default(),
+            rules: default_rules(),
+            board: Default::default(),
+            tokens: Default::default(),
+            power_ups: Default::default(),
+        }
+    }
+}
 
 pub struct Game
 {
-    pub grid: Vec<Vec<Option<u64>>>,
-    pub tokens: HashSet<Point>,
+    pub grid: Grid,
+    pub tokens: BTreeMap<Point, Token>,
+    pub power_ups: BTreeMap<Point, PowerUp>,
+    /// Tick at which each effect of a player ends, changed through `grant_effect` and `expire_effects`
+    effects: BTreeMap<(u64, PowerUpKind), u64>,
+    /// Colors with a shielded player, kept in sync with `effects`
+    shields: BTreeSet<u64>,
+    /// Owner the cells of each player get in the grid, shared by teammates
+    pub colors: BTreeMap<u64, u64>,
     pub turns_left: u64,
-    pub rng: StdRng,
+    pub rng: Isaac64Rng,
     pub gen_col: Range<usize>,
     pub gen_row: Range<usize>,
     pub game_id: u64,
-    pub players: HashMap<u64, Player>,
-    pub moved: HashMap<u64, bool>,
-    pub token_rate: f64,
+    pub players: BTreeMap<u64, Player>,
+    pub commands: BTreeMap<u64, ClientCommand>,
+    pub tick: u64,
+    pub config: GameConfig,
+    pub rules: &'static dyn Rules,
+}
+
+/// Procedural obstacles sealing a spawn off or leaving no room for the players
+/// are drawn again this many times, then dropped
+const OBSTACLE_ATTEMPTS: usize = 10;
+
+/// The board with the configured walls and the procedural obstacles of `density`
+fn draw_walls(rng: &mut Isaac64Rng, board: &BoardConfig, rows: usize, cols: usize, density: f64) -> Grid
+{
+    let mut grid = Grid::new(rows, cols);
+    for wall in board.walls.iter().filter(|w| w.x < cols && w.y < rows)
+    {
+        grid.set_wall(wall.x, wall.y, true);
+    }
+    if density > 0.0
+    {
+        let density = density.min(0.9);
+        for y in 0..rows
+        {
+            for x in 0..cols
+            {
+                let image = board.symmetry.image(&Point { x, y }, rows, cols).unwrap_or(Point { x, y });
+                if (image.y, image.x) < (y, x)
+                {
+                    continue;
+                }
+                if rng.next_f64() < density
+                {
+                    grid.set_wall(x, y, true);
+                    grid.set_wall(image.x, image.y, true);
+                }
+            }
+        }
+    }
+    if grid.wall_list().len() == rows * cols
+    {
+        grid.set_wall(0, 0, false);
+    }
+    grid
+}
+
+/// The map spawns in a random order, then cells picked by `spawn::place` for the other players
+fn place_players(rng: &mut Isaac64Rng, grid: &Grid, board: &BoardConfig, num_players: usize) -> Result<Vec<Point>, String>
+{
+    let mut spawns: Vec<Point> = board.spawns
+        .iter()
+        .filter(|p| grid.contains(p) && !grid.is_wall(p.x, p.y))
+        .cloned()
+        .collect();
+    spawns.sort();
+    spawns.dedup();
+    rng.shuffle(&mut spawns);
+    spawns.truncate(num_players);
+    let missing = num_players - spawns.len();
+    let placed = spawn::place(rng, grid, &spawns, missing, board.spawn_distance, board.symmetry)?;
+    spawns.extend(placed);
+    Ok(spawns)
 }
 
 impl Game
 {
-    pub fn new(players: HashMap<u64, Player>, total_turns: u64, game_id: u64, token_rate: f64) -> Game
+    pub fn new(players: BTreeMap<u64, Player>, game_id: u64, config: GameConfig) -> Result<Game, String>
+    {
+        let rules = rules::by_name(&config.rules).ok_or_else(|| format!("Unknown rules: {}", config.rules))?;
+        Game::with_rules(players, game_id, config, rules)
+    }
+
+    /// Fails when the board has fewer free cells than players
+    pub fn with_rules(players: BTreeMap<u64, Player>,
+                      game_id: u64,
+                      config: GameConfig,
+                      rules: &'static dyn Rules)
+                      -> Result<Game, String>
     {
         let mut players = players;
         let num_players = players.len();
-        let rows = 8 + num_players / 2;
-        let cols = rows * 2 + 1;
-        let grid: Vec<Vec<Option<u64>>> = vec![vec![None; cols]; rows];
-        let mut rng = StdRng::new().unwrap();
+        let board = &config.board;
+        let rows = board.rows.unwrap_or(board.base_rows + num_players / board.players_per_row.max(1)).max(1);
+        let cols = board.cols.unwrap_or(rows * board.cols_per_row + board.extra_cols).max(1);
+        let mut rng = Isaac64Rng::from_seed(&[config.seed]);
         let gen_row = Range::new(0, rows);
         let gen_col = Range::new(0, cols);
-        let mut moved: HashMap<u64, bool> = Default::default();
-        for player in players.values_mut()
+        let mut attempt = 0;
+        let (grid, spawns) = loop
         {
-            player.position = Point {
-                x: gen_col.ind_sample(&mut rng),
-                y: gen_row.ind_sample(&mut rng),
-            };
-            moved.insert(player.id, false);
+            let density = if attempt < OBSTACLE_ATTEMPTS { board.obstacle_density } else { 0.0 };
+            let grid = draw_walls(&mut rng, board, rows, cols, density);
+            match place_players(&mut rng, &grid, board, num_players)
+            {
+                Err(_) if density > 0.0 => (),
+                Ok(ref spawns) if density > 0.0 && !spawn::connected(&grid, spawns) => (),
+                Ok(spawns) => break (grid, spawns),
+                Err(why) => return Err(why),
+            }
+            attempt += 1;
+        };
+        let free = |p: &Point| grid.contains(p) && !grid.is_wall(p.x, p.y);
+        for (player, spawn) in players.values_mut().zip(spawns)
+        {
+            player.position = spawn;
+            player.active = true;
+            player.alive = true;
+            player.trail.clear();
         }
-        Game {
-            grid: grid,
-            tokens: Default::default(),
-            turns_left: total_turns,
-            rng: rng,
-            gen_col: gen_col,
-            gen_row: gen_row,
-            game_id: game_id,
-            players: players,
-            moved: moved,
-            token_rate: token_rate,
+        let tokens: BTreeMap<Point, Token> = board.tokens
+            .iter()
+            .filter(|p| free(p))
+            .map(|p| {
+                     (p.clone(),
+                      Token {
+                          position: p.clone(),
+                          value: 1,
+                          ttl: None,
+                      })
+                 })
+            .collect();
+        let colors = team::colors(&players);
+        Ok(Game {
+            grid,
+            tokens,
+            power_ups: Default::default(),
+            effects: Default::default(),
+            shields: Default::default(),
+            colors,
+            turns_left: config.turns,
+            rng,
+            gen_col,
+            gen_row,
+            game_id,
+            players,
+            commands: Default::default(),
+            tick: 0,
+            config,
+            rules,
+        })
+    }
+
+    pub fn overview(&self, ms_for_turn: u64) -> Overview
+    {
+        Overview {
+            game_id: self.game_id,
+            seed: self.config.seed,
+            tick: self.tick,
+            deadline_ms: 0,
+            turns_left: self.turns_left,
+            ms_for_turn,
+            grid: self.grid.to_rows(),
+            walls: self.grid.wall_list(),
+            tokens: self.tokens.values().cloned().collect(),
+            power_ups: self.power_ups.values().cloned().collect(),
+            effects: self.effects
+                .iter()
+                .map(|(&(player_id, kind), &end)| {
+                         Effect {
+                             player_id,
+                             kind,
+                             ticks_left: end - self.tick,
+                         }
+                     })
+                .collect(),
+            teams: team::scores(self),
+            players: self.players.values().cloned().collect(),
         }
     }
 
     pub fn finished(&self) -> bool
     {
-        self.turns_left == 0
+        self.end_reason().is_some()
+    }
+
+    pub fn end_reason(&self) -> Option<GameEndReason>
+    {
+        if self.turns_left == 0
+        {
+            Some(GameEndReason::TurnsExhausted)
+        }
+        else if self.active_players() == 0
+        {
+            Some(GameEndReason::NoPlayersLeft)
+        }
+        else if self.last_standing()
+        {
+            Some(GameEndReason::LastPlayerStanding)
+        }
+        else
+        {
+            None
+        }
+    }
+
+    pub fn all_submitted(&self) -> bool
+    {
+        self.players
+            .values()
+            .filter(|p| p.active)
+            .all(|p| self.commands.contains_key(&p.id))
+    }
+
+    pub fn active_players(&self) -> usize
+    {
+        self.players.values().filter(|p| p.active).count()
+    }
+
+    /// Tells whether the players still alive all belong to one of several teams
+    fn last_standing(&self) -> bool
+    {
+        let all: BTreeSet<u64> = self.colors.values().cloned().collect();
+        let alive: BTreeSet<u64> = self.players.values().filter(|p| p.alive).map(|p| self.color(p.id)).collect();
+        all.len() > 1 && alive.len() == 1
+    }
+
+    pub fn color(&self, id: u64) -> u64
+    {
+        self.colors.get(&id).cloned().unwrap_or(id)
+    }
+
+    /// Empties the cells of a team once none of its players is left to hold them
+    fn abandon(&mut self, id: u64)
+    {
+        let color = self.color(id);
+        let held = self.players.values().any(|p| p.id != id && p.active && self.color(p.id) == color);
+        if !held
+        {
+            self.grid.clear(color);
+        }
+    }
+
+    pub fn leave(&mut self, id: u64)
+    {
+        let player = match self.players.get_mut(&id)
+        {
+            Some(p) => p,
+            None => return,
+        };
+        player.active = false;
+        self.commands.remove(&id);
+        if self.config.ghost_policy == GhostPolicy::RemoveTerritory
+        {
+            player.trail.clear();
+            self.abandon(id);
+        }
+    }
+
+    /// Takes a player out of the game for good, clearing its territory and trail
+    pub fn eliminate(&mut self, id: u64)
+    {
+        let player = match self.players.get_mut(&id)
+        {
+            Some(p) => p,
+            None => return,
+        };
+        player.active = false;
+        player.alive = false;
+        player.trail.clear();
+        self.commands.remove(&id);
+        self.abandon(id);
+    }
+
+    /// Ranks the players by points, those still alive ahead of the eliminated ones
+    pub fn standings(&self) -> Vec<Standing>
+    {
+        let mut ranked: Vec<&Player> = self.players.values().collect();
+        ranked.sort_by(|a, b| b.alive.cmp(&a.alive).then(b.points.cmp(&a.points)).then(a.name.cmp(&b.name)));
+        ranked.iter()
+            .map(|p| {
+                     Standing {
+                         id: p.id,
+                         name: p.name.clone(),
+                         points: p.points,
+                     }
+                 })
+            .collect()
+    }
+
+    pub fn game_ended(&self) -> Option<GameEnded>
+    {
+        self.end_reason()
+            .map(|reason| {
+                     GameEnded {
+                         game_id: self.game_id,
+                         standings: self.standings(),
+                         reason,
+                     }
+                 })
     }
 
     pub fn tick(&mut self)
     {
-        for player in self.players.values()
+        let sped: BTreeMap<u64, ClientCommand> = self.commands
+            .iter()
+            .filter(|&(id, _)| self.has_effect(*id, PowerUpKind::Speed))
+            .map(|(id, command)| (*id, command.clone()))
+            .collect();
+        self.resolve_moves();
+        powerup::pick_up(self);
+        if !sped.is_empty()
         {
-            self.grid[player.position.y][player.position.x] = Some(player.id);
+            self.rules.paint(self);
+            self.rules.capture(self);
+            self.rules.cash_in(self);
+            // Players eliminated by the first step do not take the second one
+            self.commands = sped.into_iter().filter(|&(id, _)| self.players[&id].active && self.players[&id].alive).collect();
+            self.resolve_moves();
+            powerup::pick_up(self);
         }
-        let mut visited: HashSet<Point> = Default::default();
-        let cols = self.grid[0].len();
-        let rows = self.grid.len();
-        for y in 0..rows
+        self.rules.paint(self);
+        self.rules.capture(self);
+        self.rules.cash_in(self);
+        self.turns_left -= 1;
+        self.tick += 1;
+        for token in self.tokens.values_mut()
         {
-            for x in 0..cols
+            token.ttl = token.ttl.map(|t| t - 1);
+        }
+        self.tokens.retain(|_, t| t.ttl != Some(0));
+        let mut count = 0;
+        let mut cap = 0.01 * self.config.token_rate * (self.active_players() as f64).log2();
+        if cap > 0.5
+        {
+            cap = 0.5;
+        }
+        while self.rng.next_f64() < cap
+        {
+            count += 1;
+        }
+        if let Some(max) = self.config.tokens.max_tokens
+        {
+            count = count.min(max.saturating_sub(self.tokens.len()));
+        }
+        for _ in 0..count
+        {
+            self.spawn_token();
+        }
+        powerup::expire(self);
+        powerup::spawn(self);
+    }
+
+    pub fn has_effect(&self, id: u64, kind: PowerUpKind) -> bool
+    {
+        self.effects.get(&(id, kind)).map(|&end| self.tick < end).unwrap_or(false)
+    }
+
+    /// Tells whether a player of the team painting with `color` is shielded
+    pub fn shielded(&self, color: u64) -> bool
+    {
+        self.shields.contains(&color)
+    }
+
+    /// Gives a player the effect of `kind` until tick `end`
+    pub fn grant_effect(&mut self, id: u64, kind: PowerUpKind, end: u64)
+    {
+        self.effects.insert((id, kind), end);
+        self.refresh_shields();
+    }
+
+    /// Drops the effects that ran out
+    pub fn expire_effects(&mut self)
+    {
+        let tick = self.tick;
+        self.effects.retain(|_, end| *end > tick);
+        self.refresh_shields();
+    }
+
+    fn refresh_shields(&mut self)
+    {
+        self.shields = self.colors
+            .iter()
+            .filter(|&(&id, _)| self.has_effect(id, PowerUpKind::Shield))
+            .map(|(_, &color)| color)
+            .collect();
+    }
+
+    /// Paints a cell with the color of a player, unless it belongs to a shielded opponent
+    pub fn claim(&mut self, x: usize, y: usize, id: u64) -> bool
+    {
+        let color = self.color(id);
+        match self.grid.get(x, y)
+        {
+            Some(owner) if owner != color && self.shielded(owner) => false,
+            _ =>
             {
-                if visited.contains(&Point { x: x, y: y }) || self.grid[y][x].is_some()
-                {
-                    continue;
-                }
-                let mut to_fill: HashSet<Point> = Default::default();
-                let mut colors: HashSet<u64> = Default::default();
-                let mut queue: VecDeque<Point> = Default::default();
-                let mut valid = true;
-                queue.push_back(Point { x: x, y: y });
-                while !queue.is_empty()
+                self.grid.set(x, y, Some(color));
+                true
+            }
+        }
+    }
+
+    /// Tells whether a token or a power-up can be dropped on a cell
+    pub fn can_drop(&self, cell: &Point) -> bool
+    {
+        self.grid.contains(cell) && !self.grid.is_wall(cell.x, cell.y) && !self.tokens.contains_key(cell) &&
+        !self.power_ups.contains_key(cell) && !self.players.values().any(|p| p.position == *cell)
+    }
+
+    /// A random cell of the board, or of the token zones if there are any
+    pub fn pickup_cell(&mut self) -> Point
+    {
+        let zones = &self.config.board.token_zones;
+        if zones.is_empty()
+        {
+            Point {
+                x: self.gen_col.ind_sample(&mut self.rng),
+                y: self.gen_row.ind_sample(&mut self.rng),
+            }
+        }
+        else
+        {
+            zones[Range::new(0, zones.len()).ind_sample(&mut self.rng)].clone()
+        }
+    }
+
+    fn spawn_token(&mut self)
+    {
+        let mut cell = self.pickup_cell();
+        match self.config.tokens.policy
+        {
+            TokenPolicy::Uniform => (),
+            TokenPolicy::AwayFromPlayers =>
+            {
+                let distance = self.config.tokens.player_distance;
+                let mut attempts = 0;
+                while self.players.values().any(|p| p.active && spawn::distance(&p.position, &cell) < distance)
                 {
-                    let p = queue.pop_front().unwrap();
-                    if let Some(color) = self.grid[p.y][p.x]
+                    attempts += 1;
+                    if attempts == 10
                     {
-                        colors.insert(color);
-                        continue;
+                        return;
                     }
-                    if to_fill.contains(&p)
-                    {
-                        continue;
-                    }
-                    to_fill.insert(p.clone());
-                    visited.insert(p.clone());
-                    if p.x + 1 >= cols || (p.x as i64) - 1 < 0 || p.y + 1 >= rows || (p.y as i64) - 1 < 0
-                    {
-                        valid = false;
-                        break;
-                    }
-                    queue.push_back(Point {
-                                        x: p.x + 1,
-                                        y: p.y,
-                                    });
-                    queue.push_back(Point {
-                                        x: p.x - 1,
-                                        y: p.y,
-                                    });
-                    queue.push_back(Point {
-                                        x: p.x,
-                                        y: p.y + 1,
-                                    });
-                    queue.push_back(Point {
-                                        x: p.x,
-                                        y: p.y - 1,
-                                    });
+                    cell = self.pickup_cell();
+                }
+            }
+            TokenPolicy::CenterWeighted =>
+            {
+                let center = Point {
+                    x: self.grid.cols() / 2,
+                    y: self.grid.rows() / 2,
+                };
+                let other = self.pickup_cell();
+                if spawn::distance(&other, &center) < spawn::distance(&cell, &center)
+                {
+                    cell = other;
                 }
-                if valid && colors.len() == 1
+            }
+        }
+        if !self.can_drop(&cell)
+        {
+            return;
+        }
+        let values = &self.config.tokens.values;
+        let value = if values.is_empty()
+        {
+            1
+        }
+        else
+        {
+            values[Range::new(0, values.len()).ind_sample(&mut self.rng)]
+        };
+        let token = Token {
+            position: cell.clone(),
+            value,
+            ttl: self.config.tokens.ttl.map(|t| t.max(1)),
+        };
+        self.tokens.insert(cell, token);
+    }
+
+    fn target(&self, position: &Point, direction: &Direction) -> Option<Point>
+    {
+        let cols = self.grid.cols();
+        let rows = self.grid.rows();
+        match *direction
+        {
+            Direction::Down if position.y + 1 < rows => Some(Point { x: position.x, y: position.y + 1 }),
+            Direction::Up if position.y > 0 => Some(Point { x: position.x, y: position.y - 1 }),
+            Direction::Right if position.x + 1 < cols => Some(Point { x: position.x + 1, y: position.y }),
+            Direction::Left if position.x > 0 => Some(Point { x: position.x - 1, y: position.y }),
+            _ => None,
+        }
+        .filter(|p| !self.grid.is_wall(p.x, p.y))
+    }
+
+    /// Buffers the command of a player, it is applied by the next `tick`
+    pub fn action(&mut self, id: u64, command: ClientCommand) -> Result<(), ServerError>
+    {
+        if !self.players.get(&id).map(|p| p.active).unwrap_or(false)
+        {
+            return Err(ServerError::new(ErrorCode::NotAllowed, "Not playing in this game"));
+        }
+        if self.commands.contains_key(&id)
+        {
+            return Err(ServerError::new(ErrorCode::AlreadyMoved, "Already moved"));
+        }
+        if let ClientCommand::Move(ref direction) = command
+        {
+            if self.target(&self.players[&id].position, direction).is_none()
+            {
+                return Err(ServerError::new(ErrorCode::InvalidMove, "Moved out of grid or into a wall"));
+            }
+        }
+        self.commands.insert(id, command);
+        Ok(())
+    }
+
+    /// Applies the buffered moves all at once:
+    /// - players moving into the same cell all stay where they are;
+    /// - two players swapping their cells both stay where they are;
+    /// - a player moving into a cell occupied by a player that stays is blocked,
+    ///   and so is whoever is moving into the cell of a blocked player;
+    /// - a player can follow another one into the cell it is vacating, so
+    ///   chains of moves and closed loops of four or more players all succeed.
+    fn resolve_moves(&mut self)
+    {
+        let frozen = self.config.ghost_policy == GhostPolicy::Freeze;
+        let mut occupants: HashMap<Point, u64> = Default::default();
+        for player in self.players.values().filter(|p| p.active || (frozen && p.alive))
+        {
+            occupants.insert(player.position.clone(), player.id);
+        }
+        let mut targets: BTreeMap<u64, Point> = Default::default();
+        for (id, command) in &self.commands
+        {
+            if let ClientCommand::Move(ref direction) = *command
+            {
+                if let Some(target) = self.target(&self.players[id].position, direction)
                 {
-                    let color = colors.drain().last().unwrap();
-                    for p in to_fill.drain()
-                    {
-                        self.grid[p.y][p.x] = Some(color);
-                    }
+                    targets.insert(*id, target);
                 }
             }
         }
-        for player in self.players.values_mut()
+        self.commands.clear();
+        let mut contenders: HashMap<Point, usize> = Default::default();
+        for target in targets.values()
         {
-            *self.moved.get_mut(&player.id).unwrap() = false;
-            if self.tokens.contains(&player.position)
+            *contenders.entry(target.clone()).or_insert(0) += 1;
+        }
+        let mut blocked: Vec<u64> = Vec::new();
+        for (id, target) in &targets
+        {
+            let swapping = match occupants.get(target)
+            {
+                Some(other) => targets.get(other) == Some(&self.players[id].position),
+                None => false,
+            };
+            if contenders[target] > 1 || swapping
+            {
+                blocked.push(*id);
+            }
+        }
+        loop
+        {
+            for id in blocked.drain(..)
+            {
+                targets.remove(&id);
+            }
+            for (id, target) in &targets
             {
-                let mut count: u64 = 0;
-                self.tokens.remove(&player.position);
-                for y in 0..rows
+                if let Some(other) = occupants.get(target)
                 {
-                    for x in 0..cols
+                    if !targets.contains_key(other)
                     {
-                        match self.grid[y][x]
-                        {
-                            Some(c) if c == player.id =>
-                            {
-                                self.grid[y][x] = None;
-                                count += 1;
-                            }
-                            _ => continue,
-                        }
+                        blocked.push(*id);
                     }
                 }
-                player.points += count;
+            }
+            if blocked.is_empty()
+            {
+                break;
             }
         }
-        self.turns_left -= 1;
-        let mut count = 0;
-        let mut cap = 0.01 * self.token_rate * (self.players.len() as f64).log2();
-        if cap > 0.5
+        for (id, target) in targets
         {
-            cap = 0.5;
+            self.players.get_mut(&id).unwrap().position = target;
         }
-        while self.rng.next_f64() < cap
+    }
+}
+
+/// A game of `players` for the tests, without random tokens, on a `rows` by `cols` board when
+/// given and with player `i + 1` standing at `positions[i]`
+#[cfg(test)]
+pub fn test_game(players: BTreeMap<u64, Player>, rows: Option<usize>, cols: Option<usize>, positions: &[(usize, usize)]) -> Game
+{
+    let mut config = GameConfig {
+        turns: 100,
+        token_rate: 0.0,
+        seed: 42,
+        ..Default::default()
+    };
+    config.board.rows = rows;
+    config.board.cols = cols;
+    let mut game = Game::new(players, 0, config).unwrap();
+    for (i, &(x, y)) in positions.iter().enumerate()
+    {
+        game.players.get_mut(&(i as u64 + 1)).unwrap().position = Point { x, y };
+    }
+    game
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn players(count: usize) -> BTreeMap<u64, Player>
+    {
+        let mut players: BTreeMap<u64, Player> = Default::default();
+        for id in 1..count as u64 + 1
         {
-            count += 1;
+            players.insert(id, Player::new(id, format!("p{}", id)));
         }
-        for _ in 0..count
+        players
+    }
+
+    fn ghost_game(ghost_policy: GhostPolicy) -> Game
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (2, 1)]);
+        game.config.ghost_policy = ghost_policy;
+        game.grid.set(0, 1, Some(1));
+        game.grid.set(1, 0, Some(1));
+        game
+    }
+
+    fn play(game: &mut Game, moves: &[(u64, Direction)]) -> Vec<(usize, usize)>
+    {
+        for &(id, ref direction) in moves
+        {
+            game.action(id, ClientCommand::Move(direction.clone())).unwrap();
+        }
+        game.tick();
+        game.players.values().map(|p| (p.position.x, p.position.y)).collect()
+    }
+
+    fn owned_cells(game: &Game, id: u64) -> usize
+    {
+        game.grid.count(id)
+    }
+
+    #[test]
+    fn same_seed_plays_the_same_game()
+    {
+        let config = GameConfig {
+            turns: 30,
+            token_rate: 50.0,
+            seed: 7,
+            ..Default::default()
+        };
+        let mut first = Game::new(players(4), 0, config.clone()).unwrap();
+        let mut second = Game::new(players(4), 0, config).unwrap();
+        let directions = [Direction::Up, Direction::Right, Direction::Down, Direction::Left];
+        let mut saw_tokens = false;
+        while !first.finished()
+        {
+            for id in 1..5
+            {
+                let direction = directions[(first.tick as usize + id as usize) % directions.len()].clone();
+                let _ = first.action(id, ClientCommand::Move(direction.clone()));
+                let _ = second.action(id, ClientCommand::Move(direction));
+            }
+            first.tick();
+            second.tick();
+            assert_eq!(first.overview(0), second.overview(0));
+            saw_tokens |= !first.tokens.is_empty();
+        }
+        assert!(saw_tokens);
+        assert!(second.finished());
+    }
+
+    #[test]
+    fn other_seeds_place_the_players_elsewhere()
+    {
+        let positions = |seed| {
+            let config = GameConfig { seed, ..Default::default() };
+            let game = Game::new(players(4), 0, config).unwrap();
+            game.players.values().map(|p| p.position.clone()).collect::<Vec<Point>>()
+        };
+        assert_eq!(positions(1), positions(1));
+        assert!((2..10).any(|seed| positions(seed) != positions(1)));
+    }
+
+    #[test]
+    fn games_can_be_sent_to_another_thread()
+    {
+        fn assert_send<T: Send>() {}
+        assert_send::<Game>();
+    }
+
+    #[test]
+    fn frozen_ghost_keeps_territory_and_blocks()
+    {
+        let mut game = ghost_game(GhostPolicy::Freeze);
+        game.tick();
+        let before = owned_cells(&game, 1);
+        game.leave(1);
+        assert!(!game.players[&1].active);
+        assert_eq!(owned_cells(&game, 1), before);
+        assert_eq!(play(&mut game, &[(2, Direction::Left)]), vec![(1, 1), (2, 1)]);
+        assert_eq!(owned_cells(&game, 1), before);
+    }
+
+    #[test]
+    fn removed_ghost_loses_territory()
+    {
+        let mut game = ghost_game(GhostPolicy::RemoveTerritory);
+        game.tick();
+        assert!(owned_cells(&game, 1) > 0);
+        game.leave(1);
+        assert_eq!(owned_cells(&game, 1), 0);
+        assert!(game.action(2, ClientCommand::Move(Direction::Left)).is_ok());
+        game.tick();
+        assert_eq!(owned_cells(&game, 1), 0);
+    }
+
+    #[test]
+    fn ghost_cannot_act()
+    {
+        for &policy in &[GhostPolicy::Freeze, GhostPolicy::RemoveTerritory]
+        {
+            let mut game = ghost_game(policy);
+            game.leave(1);
+            let result = game.action(1, ClientCommand::Nothing);
+            assert_eq!(result.unwrap_err().code, ErrorCode::NotAllowed);
+            assert!(!game.commands.contains_key(&1));
+        }
+    }
+
+    #[test]
+    fn game_ends_when_every_player_left()
+    {
+        let mut game = ghost_game(GhostPolicy::Freeze);
+        game.leave(1);
+        assert!(!game.finished());
+        game.leave(2);
+        assert_eq!(game.end_reason(), Some(GameEndReason::NoPlayersLeft));
+        assert_eq!(game.standings().len(), 2);
+    }
+
+    #[test]
+    fn moves_are_buffered_until_tick()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (4, 4)]);
+        game.action(1, ClientCommand::Move(Direction::Right)).unwrap();
+        assert_eq!(game.players[&1].position, Point { x: 1, y: 1 });
+        let again = game.action(1, ClientCommand::Nothing);
+        assert_eq!(again.unwrap_err().code, ErrorCode::AlreadyMoved);
+        game.tick();
+        assert_eq!(game.players[&1].position, Point { x: 2, y: 1 });
+    }
+
+    #[test]
+    fn out_of_grid_is_rejected_immediately()
+    {
+        let mut game = test_game(players(2), None, None, &[(0, 0), (4, 4)]);
+        let result = game.action(1, ClientCommand::Move(Direction::Up));
+        assert_eq!(result.unwrap_err().code, ErrorCode::InvalidMove);
+        assert!(game.action(1, ClientCommand::Move(Direction::Down)).is_ok());
+    }
+
+    #[test]
+    fn contested_cell_blocks_everyone()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (3, 1)]);
+        assert_eq!(play(&mut game, &[(1, Direction::Right), (2, Direction::Left)]), vec![(1, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn swap_is_blocked()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (2, 1)]);
+        assert_eq!(play(&mut game, &[(1, Direction::Right), (2, Direction::Left)]), vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn standing_player_blocks()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (2, 1)]);
+        assert_eq!(play(&mut game, &[(1, Direction::Right)]), vec![(1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn chain_follows_vacated_cells()
+    {
+        let mut game = test_game(players(3), None, None, &[(1, 1), (2, 1), (3, 1)]);
+        let moves = [(1, Direction::Right), (2, Direction::Right), (3, Direction::Right)];
+        assert_eq!(play(&mut game, &moves), vec![(2, 1), (3, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn blocked_head_blocks_the_chain()
+    {
+        let mut game = test_game(players(4), None, None, &[(1, 1), (2, 1), (3, 1), (4, 2)]);
+        let moves = [(1, Direction::Right), (2, Direction::Right), (3, Direction::Right), (4, Direction::Up)];
+        assert_eq!(play(&mut game, &moves), vec![(1, 1), (2, 1), (3, 1), (4, 2)]);
+    }
+
+    #[test]
+    fn loop_of_four_rotates()
+    {
+        let mut game = test_game(players(4), None, None, &[(1, 1), (2, 1), (2, 2), (1, 2)]);
+        let moves = [(1, Direction::Right), (2, Direction::Down), (3, Direction::Left), (4, Direction::Up)];
+        assert_eq!(play(&mut game, &moves), vec![(2, 1), (2, 2), (1, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn removed_ghost_does_not_block()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (2, 1)]);
+        game.config.ghost_policy = GhostPolicy::RemoveTerritory;
+        game.leave(2);
+        assert_eq!(play(&mut game, &[(1, Direction::Right)]), vec![(2, 1), (2, 1)]);
+    }
+
+    fn drop_power_up(game: &mut Game, x: usize, y: usize, kind: PowerUpKind)
+    {
+        let position = Point { x, y };
+        game.power_ups.insert(position.clone(),
+                              PowerUp {
+                                  position,
+                                  kind,
+                                  ttl: None,
+                              });
+    }
+
+    #[test]
+    fn speed_moves_twice()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (6, 6)]);
+        drop_power_up(&mut game, 2, 1, PowerUpKind::Speed);
+        assert_eq!(play(&mut game, &[(1, Direction::Right)]), vec![(2, 1), (6, 6)]);
+        assert!(game.has_effect(1, PowerUpKind::Speed));
+        assert_eq!(play(&mut game, &[(1, Direction::Right)]), vec![(4, 1), (6, 6)]);
+        assert_eq!(game.grid.get(3, 1), Some(1));
+        assert_eq!(play(&mut game, &[(1, Direction::Down)]), vec![(4, 3), (6, 6)]);
+    }
+
+    #[test]
+    fn speed_collects_the_token_it_steps_over()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (6, 6)]);
+        drop_power_up(&mut game, 2, 1, PowerUpKind::Speed);
+        play(&mut game, &[(1, Direction::Right)]);
+        put_token(&mut game, 3, 1, 1, None);
+        assert_eq!(play(&mut game, &[(1, Direction::Right)]), vec![(4, 1), (6, 6)]);
+        assert!(game.tokens.is_empty());
+        assert!(game.players[&1].points > 0);
+    }
+
+    #[test]
+    fn shield_protects_territory()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (3, 1)]);
+        game.config.rules = String::from("steal-trail");
+        game.rules = rules::by_name("steal-trail").unwrap();
+        game.grid.set(2, 1, Some(1));
+        drop_power_up(&mut game, 1, 2, PowerUpKind::Shield);
+        play(&mut game, &[(1, Direction::Down)]);
+        assert!(game.has_effect(1, PowerUpKind::Shield));
+        play(&mut game, &[(2, Direction::Left)]);
+        assert_eq!(game.grid.get(2, 1), Some(1));
+        assert_eq!(game.overview(0).effects[0].ticks_left, 9);
+    }
+
+    #[test]
+    fn bomb_clears_opponents_around()
+    {
+        let mut game = test_game(players(2), None, None, &[(1, 1), (6, 6)]);
+        for &(x, y) in &[(1, 2), (2, 2), (3, 2), (3, 3), (4, 4)]
         {
-            self.tokens.insert(Point {
-                                   x: self.gen_col.ind_sample(&mut self.rng),
-                                   y: self.gen_row.ind_sample(&mut self.rng),
-                               });
+            game.grid.set(x, y, Some(2));
         }
+        game.grid.set(3, 1, Some(1));
+        drop_power_up(&mut game, 2, 1, PowerUpKind::Bomb);
+        play(&mut game, &[(1, Direction::Right)]);
+        assert!(game.power_ups.is_empty());
+        assert_eq!(owned_cells(&game, 2), 3);
+        assert_eq!(game.grid.get(3, 3), Some(2));
+        assert_eq!(game.grid.get(3, 1), Some(1));
     }
 
-    pub fn action(&mut self, id: u64, command: ClientCommand) -> Result<(), String>
+    #[test]
+    fn obstacles_never_seal_a_spawn_off()
     {
-        let ok = match self.moved.get(&id)
+        for seed in 0..50
         {
-            Some(x) => !x,
-            None => false,
+            let mut config = GameConfig {
+                seed,
+                ..Default::default()
+            };
+            config.board.rows = Some(8);
+            config.board.cols = Some(8);
+            config.board.obstacle_density = 0.5;
+            let game = Game::new(players(4), 0, config).unwrap();
+            let spawns: Vec<Point> = game.players.values().map(|p| p.position.clone()).collect();
+            assert!(spawn::connected(&game.grid, &spawns), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn obstacles_leaving_no_room_are_dropped()
+    {
+        let mut config = GameConfig {
+            seed: 42,
+            ..Default::default()
         };
-        if !ok
+        config.board.rows = Some(3);
+        config.board.cols = Some(3);
+        config.board.obstacle_density = 0.9;
+        let game = Game::new(players(9), 0, config).unwrap();
+        assert!(game.grid.wall_list().is_empty());
+    }
+
+    fn put_token(game: &mut Game, x: usize, y: usize, value: u64, ttl: Option<u64>)
+    {
+        let position = Point { x, y };
+        game.tokens.insert(position.clone(), Token { position, value, ttl });
+    }
+
+    #[test]
+    fn tokens_expire_after_their_ttl()
+    {
+        let mut game = test_game(players(2), Some(5), Some(5), &[(0, 0), (4, 4)]);
+        put_token(&mut game, 2, 2, 1, Some(2));
+        put_token(&mut game, 3, 2, 1, None);
+        game.tick();
+        assert_eq!(game.tokens[&Point { x: 2, y: 2 }].ttl, Some(1));
+        game.tick();
+        assert!(!game.tokens.contains_key(&Point { x: 2, y: 2 }));
+        assert_eq!(game.tokens.len(), 1);
+    }
+
+    #[test]
+    fn token_value_multiplies_the_cashed_cells()
+    {
+        let mut game = test_game(players(2), Some(5), Some(5), &[(1, 1), (4, 4)]);
+        game.grid.set(0, 1, Some(1));
+        game.grid.set(0, 0, Some(1));
+        put_token(&mut game, 1, 1, 3, None);
+        game.tick();
+        assert_eq!(game.players[&1].points, 9);
+        assert_eq!(game.grid.count(1), 0);
+        assert!(game.tokens.is_empty());
+    }
+
+    #[test]
+    fn max_tokens_caps_the_board_and_expired_slots_refill_at_once()
+    {
+        let mut game = test_game(players(2), Some(9), Some(9), &[(0, 0), (8, 8)]);
+        game.config.token_rate = 100.0;
+        game.config.tokens.max_tokens = Some(1);
+        game.config.tokens.ttl = Some(1);
+        let mut present: Vec<bool> = Vec::new();
+        for _ in 0..40
+        {
+            game.tick();
+            assert!(game.tokens.len() <= 1);
+            present.push(!game.tokens.is_empty());
+        }
+        assert!(present.windows(2).any(|w| w[0] && w[1]));
+    }
+
+    #[test]
+    fn tokens_never_spawn_under_a_player()
+    {
+        let mut game = test_game(players(2), Some(5), Some(5), &[(2, 2), (4, 4)]);
+        game.config.board.token_zones = vec![Point { x: 2, y: 2 }, Point { x: 4, y: 4 }];
+        for _ in 0..50
         {
-            return Err(String::from("Already moved"));
+            game.spawn_token();
         }
-        *self.moved.get_mut(&id).unwrap() = true;
-        let mut positions: HashSet<Point> = Default::default();
-        for pos in self.players.values().map(|p| p.position.clone())
+        assert!(game.tokens.is_empty());
+    }
+
+    #[test]
+    fn away_from_players_keeps_the_distance()
+    {
+        let mut game = test_game(players(2), Some(9), Some(9), &[(4, 4), (0, 0)]);
+        game.config.tokens.policy = TokenPolicy::AwayFromPlayers;
+        game.config.tokens.player_distance = 3;
+        for _ in 0..200
         {
-            positions.insert(pos);
+            game.spawn_token();
         }
-        let player = self.players.get_mut(&id).unwrap();
-        let cols = self.grid[0].len();
-        let rows = self.grid.len();
-        match command
+        assert!(!game.tokens.is_empty());
+        for cell in game.tokens.keys()
         {
-            ClientCommand::Nothing => Ok(()),
-            ClientCommand::Move(direction) =>
+            assert!(game.players.values().all(|p| spawn::distance(&p.position, cell) >= 3));
+        }
+    }
+
+    #[test]
+    fn center_weighted_tokens_gather_near_the_center()
+    {
+        let mean_distance = |policy: TokenPolicy| {
+            let mut game = test_game(players(2), Some(21), Some(21), &[(0, 0), (20, 20)]);
+            game.config.tokens.policy = policy;
+            let mut total = 0;
+            for _ in 0..500
             {
-                match direction
-                {
-                    Direction::Down if player.position.y + 1 < rows &&
-                                       !positions.contains(&Point {
-                                                                x: player.position.x,
-                                                                y: player.position.y + 1,
-                                                            }) =>
-                    {
-                        player.position.y += 1;
-                        Ok(())
-                    }
-                    Direction::Up if (player.position.y as i64) - 1 >= 0 &&
-                                     !positions.contains(&Point {
-                                                              x: player.position.x,
-                                                              y: player.position.y - 1,
-                                                          }) =>
-                    {
-                        player.position.y -= 1;
-                        Ok(())
-                    }
-                    Direction::Right if player.position.x + 1 < cols &&
-                                        !positions.contains(&Point {
-                                                                 x: player.position.x + 1,
-                                                                 y: player.position.y,
-                                                             }) =>
-                    {
-                        player.position.x += 1;
-                        Ok(())
-                    }
-                    Direction::Left if (player.position.x as i64) - 1 >= 0 &&
-                                       !positions.contains(&Point {
-                                                                x: player.position.x - 1,
-                                                                y: player.position.y,
-                                                            }) =>
-                    {
-                        player.position.x -= 1;
-                        Ok(())
-                    }
-                    _ =>
-                    {
-                        *self.moved.get_mut(&id).unwrap() = false;
-                        Err(String::from("Moved out of grid or in a cell already taken"))
-                    }
-                }
+                game.spawn_token();
+                total += game.tokens.keys().map(|t| spawn::distance(t, &Point { x: 10, y: 10 })).sum::<usize>();
+                game.tokens.clear();
             }
-
-        }
+            total
+        };
+        assert!(mean_distance(TokenPolicy::CenterWeighted) * 10 < mean_distance(TokenPolicy::Uniform) * 9);
     }
 }
@@ -0,0 +1,535 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::Point;
+use std::collections::HashMap;
+use std::mem;
+
+/// The board as flat row-major arrays of owners and walls.
+/// Every change of owner goes through `set`, which keeps the list of cells of each player
+/// up to date, so counting or clearing a territory never scans the whole board.
+#[derive(Clone)]
+pub struct Grid
+{
+    rows: usize,
+    cols: usize,
+    cells: Vec<Option<u64>>,
+    walls: Vec<bool>,
+    owned: HashMap<u64, Vec<usize>>,
+    /// Position of each owned cell in the list of its owner
+    slots: Vec<usize>,
+    /// Scratch space of the flood fills, kept between ticks to avoid allocations
+    seen: Vec<u32>,
+    epoch: u32,
+    stack: Vec<usize>,
+    region: Vec<usize>,
+    labels: Vec<usize>,
+}
+
+/// Maximal area of cells with the same owner, found by `enclosures`
+struct Blob
+{
+    owner: Option<u64>,
+    /// Its cells are `region[first..last]`
+    first: usize,
+    last: usize,
+    open: bool,
+}
+
+impl Grid
+{
+    pub fn new(rows: usize, cols: usize) -> Grid
+    {
+        Grid {
+            rows,
+            cols,
+            cells: vec![None; rows * cols],
+            walls: vec![false; rows * cols],
+            owned: Default::default(),
+            slots: vec![0; rows * cols],
+            seen: vec![0; rows * cols],
+            epoch: 0,
+            stack: Vec::new(),
+            region: Vec::new(),
+            labels: vec![0; rows * cols],
+        }
+    }
+
+    pub fn rows(&self) -> usize
+    {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize
+    {
+        self.cols
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> usize
+    {
+        y * self.cols + x
+    }
+
+    pub fn point(&self, index: usize) -> Point
+    {
+        Point {
+            x: index % self.cols,
+            y: index / self.cols,
+        }
+    }
+
+    pub fn contains(&self, p: &Point) -> bool
+    {
+        p.x < self.cols && p.y < self.rows
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<u64>
+    {
+        self.cells[self.index(x, y)]
+    }
+
+    pub fn is_wall(&self, x: usize, y: usize) -> bool
+    {
+        self.walls[self.index(x, y)]
+    }
+
+    pub fn set_wall(&mut self, x: usize, y: usize, wall: bool)
+    {
+        let i = self.index(x, y);
+        self.walls[i] = wall;
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, owner: Option<u64>)
+    {
+        let i = self.index(x, y);
+        self.set_at(i, owner);
+    }
+
+    pub fn set_at(&mut self, index: usize, owner: Option<u64>)
+    {
+        if self.cells[index] == owner
+        {
+            return;
+        }
+        if let Some(old) = self.cells[index]
+        {
+            let list = self.owned.get_mut(&old).unwrap();
+            let slot = self.slots[index];
+            list.swap_remove(slot);
+            if slot < list.len()
+            {
+                self.slots[list[slot]] = slot;
+            }
+        }
+        if let Some(new) = owner
+        {
+            let list = self.owned.entry(new).or_default();
+            self.slots[index] = list.len();
+            list.push(index);
+        }
+        self.cells[index] = owner;
+    }
+
+    /// Indexes of the cells of a player, in no particular order
+    pub fn cells_of(&self, id: u64) -> &[usize]
+    {
+        self.owned.get(&id).map(|l| l.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn count(&self, id: u64) -> usize
+    {
+        self.cells_of(id).len()
+    }
+
+    /// Empties every cell of a player and returns how many there were
+    pub fn clear(&mut self, id: u64) -> usize
+    {
+        let list = self.owned.remove(&id).unwrap_or_default();
+        for &i in &list
+        {
+            self.cells[i] = None;
+        }
+        list.len()
+    }
+
+    /// Starts a new flood fill, every cell becomes unseen
+    fn next_epoch(&mut self)
+    {
+        self.epoch = self.epoch.wrapping_add(1);
+        if self.epoch == 0
+        {
+            self.seen.iter_mut().for_each(|s| *s = 0);
+            self.epoch = 1;
+        }
+    }
+
+    fn on_edge(&self, i: usize) -> bool
+    {
+        let (x, y) = (i % self.cols, i / self.cols);
+        x == 0 || y == 0 || x + 1 == self.cols || y + 1 == self.rows
+    }
+
+    /// The four neighbours of a cell, each one flagged with whether it is inside the board
+    fn neighbours(&self, i: usize) -> [(bool, usize); 4]
+    {
+        let (x, y) = (i % self.cols, i / self.cols);
+        [(x > 0, i.wrapping_sub(1)),
+         (x + 1 < self.cols, i + 1),
+         (y > 0, i.wrapping_sub(self.cols)),
+         (y + 1 < self.rows, i + self.cols)]
+    }
+
+    /// Gives to a player every empty area that only its cells enclose.
+    /// Walls are boundaries, an area touching the edge of the board is never enclosed.
+    pub fn capture(&mut self)
+    {
+        self.next_epoch();
+        let mut stack = mem::take(&mut self.stack);
+        let mut region = mem::take(&mut self.region);
+        for start in 0..self.cells.len()
+        {
+            if self.seen[start] == self.epoch || self.cells[start].is_some() || self.walls[start]
+            {
+                continue;
+            }
+            self.seen[start] = self.epoch;
+            stack.push(start);
+            region.clear();
+            let mut open = false;
+            let mut owner: Option<u64> = None;
+            let mut mixed = false;
+            while let Some(i) = stack.pop()
+            {
+                region.push(i);
+                open |= self.on_edge(i);
+                for &(inside, n) in &self.neighbours(i)
+                {
+                    if !inside || self.walls[n]
+                    {
+                        continue;
+                    }
+                    match self.cells[n]
+                    {
+                        Some(color) =>
+                        {
+                            if owner.is_some() && owner != Some(color)
+                            {
+                                mixed = true;
+                            }
+                            owner = Some(color);
+                        }
+                        None if self.seen[n] != self.epoch =>
+                        {
+                            self.seen[n] = self.epoch;
+                            stack.push(n);
+                        }
+                        None => (),
+                    }
+                }
+            }
+            if open || mixed
+            {
+                continue;
+            }
+            if let Some(color) = owner
+            {
+                for &i in &region
+                {
+                    self.set_at(i, Some(color));
+                }
+            }
+        }
+        self.stack = stack;
+        self.region = region;
+    }
+
+    /// The cells that each of `colors` closes off from the edge together with the walls,
+    /// enemy cells included, as pairs of cell and color.
+    /// A cell inside several loops goes to the smallest area, then to the lowest color.
+    ///
+    /// The board is split once into areas of a single owner; the loops of each color
+    /// are then found on the graph of those areas instead of on the cells.
+    pub fn enclosures(&mut self, colors: &[u64]) -> Vec<(usize, u64)>
+    {
+        self.next_epoch();
+        let mut stack = mem::take(&mut self.stack);
+        let mut region = mem::take(&mut self.region);
+        region.clear();
+        let mut blobs: Vec<Blob> = Vec::new();
+        let mut links: Vec<(usize, usize)> = Vec::new();
+        for start in 0..self.cells.len()
+        {
+            if self.seen[start] == self.epoch || self.walls[start]
+            {
+                continue;
+            }
+            let label = blobs.len();
+            let owner = self.cells[start];
+            let first = region.len();
+            let mut open = false;
+            self.seen[start] = self.epoch;
+            self.labels[start] = label;
+            stack.push(start);
+            while let Some(i) = stack.pop()
+            {
+                region.push(i);
+                open |= self.on_edge(i);
+                for &(inside, n) in &self.neighbours(i)
+                {
+                    if !inside || self.walls[n]
+                    {
+                        continue;
+                    }
+                    if self.seen[n] == self.epoch
+                    {
+                        if self.labels[n] != label
+                        {
+                            links.push((label, self.labels[n]));
+                            links.push((self.labels[n], label));
+                        }
+                    }
+                    else if self.cells[n] == owner
+                    {
+                        self.seen[n] = self.epoch;
+                        self.labels[n] = label;
+                        stack.push(n);
+                    }
+                }
+            }
+            blobs.push(Blob {
+                           owner,
+                           first,
+                           last: region.len(),
+                           open,
+                       });
+        }
+        links.sort_unstable();
+        links.dedup();
+        // The links of blob `b` are `links[starts[b]..starts[b + 1]]`
+        let mut starts: Vec<usize> = vec![0; blobs.len() + 1];
+        for &(from, _) in &links
+        {
+            starts[from + 1] += 1;
+        }
+        for b in 0..blobs.len()
+        {
+            starts[b + 1] += starts[b];
+        }
+        let mut claims: Vec<Option<(usize, u64)>> = vec![None; blobs.len()];
+        let mut visited: Vec<usize> = vec![usize::MAX; blobs.len()];
+        let mut area: Vec<usize> = Vec::new();
+        for (k, &color) in colors.iter().enumerate()
+        {
+            for b in (0..blobs.len()).filter(|&b| blobs[b].owner == Some(color))
+            {
+                for &(_, next) in &links[starts[b]..starts[b + 1]]
+                {
+                    if blobs[next].owner == Some(color) || visited[next] == k
+                    {
+                        continue;
+                    }
+                    visited[next] = k;
+                    stack.push(next);
+                    area.clear();
+                    let mut size = 0;
+                    let mut open = false;
+                    while let Some(a) = stack.pop()
+                    {
+                        area.push(a);
+                        size += blobs[a].last - blobs[a].first;
+                        open |= blobs[a].open;
+                        for &(_, n) in &links[starts[a]..starts[a + 1]]
+                        {
+                            if blobs[n].owner != Some(color) && visited[n] != k
+                            {
+                                visited[n] = k;
+                                stack.push(n);
+                            }
+                        }
+                    }
+                    if open
+                    {
+                        continue;
+                    }
+                    for &a in &area
+                    {
+                        let claim = claims[a].map_or((size, color), |c| c.min((size, color)));
+                        claims[a] = Some(claim);
+                    }
+                }
+            }
+        }
+        let mut cells: Vec<(usize, u64)> = Vec::new();
+        for (blob, claim) in blobs.iter().zip(&claims)
+        {
+            if let Some((_, color)) = *claim
+            {
+                cells.extend(region[blob.first..blob.last].iter().map(|&i| (i, color)));
+            }
+        }
+        self.stack = stack;
+        self.region = region;
+        cells
+    }
+
+    /// Gives each enclosed empty area to the color, among `colors`, owning most of its border.
+    /// An area stays empty when no color has a strict majority.
+    pub fn fill_pockets(&mut self, colors: &[u64])
+    {
+        self.next_epoch();
+        let mut stack = mem::take(&mut self.stack);
+        let mut region = mem::take(&mut self.region);
+        let mut border: Vec<usize> = Vec::new();
+        let mut owners: Vec<u64> = Vec::new();
+        for start in 0..self.cells.len()
+        {
+            if self.seen[start] == self.epoch || self.cells[start].is_some() || self.walls[start]
+            {
+                continue;
+            }
+            self.seen[start] = self.epoch;
+            stack.push(start);
+            region.clear();
+            border.clear();
+            let mut open = false;
+            while let Some(i) = stack.pop()
+            {
+                region.push(i);
+                open |= self.on_edge(i);
+                for &(inside, n) in &self.neighbours(i)
+                {
+                    if !inside || self.walls[n]
+                    {
+                        continue;
+                    }
+                    if self.cells[n].is_some()
+                    {
+                        border.push(n);
+                    }
+                    else if self.seen[n] != self.epoch
+                    {
+                        self.seen[n] = self.epoch;
+                        stack.push(n);
+                    }
+                }
+            }
+            if open
+            {
+                continue;
+            }
+            border.sort_unstable();
+            border.dedup();
+            owners.clear();
+            owners.extend(border.iter().filter_map(|&i| self.cells[i]).filter(|c| colors.contains(c)));
+            owners.sort_unstable();
+            let (mut winner, mut most, mut tied, mut run) = (None, 0, false, 0);
+            for (k, &color) in owners.iter().enumerate()
+            {
+                run = if k > 0 && owners[k - 1] == color { run + 1 } else { 1 };
+                if run > most
+                {
+                    winner = Some(color);
+                    most = run;
+                    tied = false;
+                }
+                else if run == most
+                {
+                    tied = true;
+                }
+            }
+            if tied
+            {
+                continue;
+            }
+            if let Some(color) = winner
+            {
+                for &i in &region
+                {
+                    self.set_at(i, Some(color));
+                }
+            }
+        }
+        self.stack = stack;
+        self.region = region;
+    }
+
+    /// The owners row by row, as sent to the clients
+    pub fn to_rows(&self) -> Vec<Vec<Option<u64>>>
+    {
+        self.cells.chunks(self.cols).map(|row| row.to_vec()).collect()
+    }
+
+    pub fn wall_list(&self) -> Vec<Point>
+    {
+        (0..self.walls.len()).filter(|&i| self.walls[i]).map(|i| self.point(i)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn counters_follow_changes()
+    {
+        let mut grid = Grid::new(3, 4);
+        grid.set(0, 0, Some(1));
+        grid.set(1, 0, Some(1));
+        grid.set(2, 0, Some(2));
+        grid.set(0, 0, Some(2));
+        grid.set(3, 2, Some(1));
+        grid.set(1, 0, None);
+        assert_eq!(grid.count(1), 1);
+        assert_eq!(grid.count(2), 2);
+        assert_eq!(grid.clear(2), 2);
+        assert_eq!(grid.count(2), 0);
+        assert_eq!(grid.get(0, 0), None);
+        grid.set(0, 0, Some(2));
+        assert_eq!(grid.cells_of(2), &[0]);
+    }
+
+    #[test]
+    fn capture_fills_only_single_owner_enclosures()
+    {
+        let mut grid = Grid::new(5, 5);
+        for &(x, y) in &[(1, 1), (2, 1), (3, 1), (1, 2), (3, 2), (1, 3), (3, 3)]
+        {
+            grid.set(x, y, Some(1));
+        }
+        grid.set(2, 3, Some(2));
+        grid.capture();
+        assert_eq!(grid.get(2, 2), None);
+        grid.set(2, 3, Some(1));
+        grid.capture();
+        assert_eq!(grid.get(2, 2), Some(1));
+        assert_eq!(grid.count(1), 9);
+        assert_eq!(grid.get(0, 0), None);
+    }
+
+    #[test]
+    fn walls_bound_enclosures()
+    {
+        let mut grid = Grid::new(5, 5);
+        grid.set_wall(2, 1, true);
+        grid.set(1, 2, Some(1));
+        grid.set(3, 2, Some(1));
+        grid.set(2, 3, Some(1));
+        grid.capture();
+        assert_eq!(grid.get(2, 2), Some(1));
+        assert!(!grid.is_wall(2, 2));
+        let mut walled = Grid::new(5, 5);
+        for &(x, y) in &[(2, 1), (1, 2), (3, 2), (2, 3)]
+        {
+            walled.set_wall(x, y, true);
+        }
+        walled.capture();
+        assert_eq!(walled.get(2, 2), None);
+    }
+}
@@ -0,0 +1,228 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+pub struct Guest
+{
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+fn power_up_name(kind: PowerUpKind) -> &'static str
+{
+    match kind
+    {
+        PowerUpKind::Speed => "SPEED",
+        PowerUpKind::Shield => "SHIELD",
+        PowerUpKind::Bomb => "BOMB",
+    }
+}
+
+impl Drop for Guest
+{
+    fn drop(&mut self)
+    {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Guest
+{
+    pub fn spawn(program: &str, args: &[String]) -> io::Result<Guest>
+    {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(Guest {
+               child,
+               stdin,
+               stdout,
+           })
+    }
+
+    fn read_line(&mut self) -> io::Result<String>
+    {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0
+        {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Guest program closed its output"));
+        }
+        Ok(line)
+    }
+
+    pub fn read_name(&mut self) -> io::Result<String>
+    {
+        self.read_line()
+    }
+
+    #[allow(clippy::many_single_char_names, clippy::needless_range_loop)]
+    pub fn send_overview(&mut self, overview: &Overview) -> io::Result<()>
+    {
+        let stdin = &mut self.stdin;
+        let n = overview.players.len();
+        let r = overview.grid.len();
+        let c = overview.grid[0].len();
+        let t = overview.tokens.len();
+        let e = overview.turns_left;
+        let m = overview.ms_for_turn;
+        let walls: HashSet<&Point> = overview.walls.iter().collect();
+        writeln!(stdin, "{} {} {} {} {} {}", n, r, c, t, e, m)?;
+        for p in &overview.players
+        {
+            write!(stdin,
+                   "{} {} {} {} {} {} {} {}",
+                   p.id,
+                   p.name,
+                   p.points,
+                   p.position.x,
+                   p.position.y,
+                   p.alive as u8,
+                   p.team.as_deref().unwrap_or("-"),
+                   p.trail.len())?;
+            for cell in &p.trail
+            {
+                write!(stdin, " {} {}", cell.x, cell.y)?;
+            }
+            writeln!(stdin)?;
+        }
+        for (y, rows) in overview.grid.iter().enumerate()
+        {
+            for pos in 0..rows.len()
+            {
+                if walls.contains(&Point { x: pos, y })
+                {
+                    write!(stdin, "{}", -2)?;
+                }
+                else if let Some(value) = rows[pos]
+                {
+                    write!(stdin, "{}", value)?;
+                }
+                else
+                {
+                    write!(stdin, "{}", -1)?;
+                }
+                if pos != rows.len() - 1
+                {
+                    write!(stdin, " ")?;
+                }
+            }
+            writeln!(stdin)?;
+        }
+        for token in &overview.tokens
+        {
+            let ttl = token.ttl.map(|t| t as i64).unwrap_or(-1);
+            writeln!(stdin, "{} {} {} {}", token.position.x, token.position.y, token.value, ttl)?;
+        }
+        writeln!(stdin, "{} {}", overview.power_ups.len(), overview.effects.len())?;
+        for power_up in &overview.power_ups
+        {
+            let ttl = power_up.ttl.map(|t| t as i64).unwrap_or(-1);
+            writeln!(stdin, "{} {} {} {}", power_up.position.x, power_up.position.y, power_up_name(power_up.kind), ttl)?;
+        }
+        for effect in &overview.effects
+        {
+            writeln!(stdin, "{} {} {}", effect.player_id, power_up_name(effect.kind), effect.ticks_left)?;
+        }
+        writeln!(stdin, "{}", overview.teams.len())?;
+        for team in &overview.teams
+        {
+            writeln!(stdin, "{} {} {} {}", team.name, team.color, team.points, team.cells)?;
+        }
+        stdin.flush()
+    }
+
+    pub fn send_game_ended(&mut self, ended: &GameEnded) -> io::Result<()>
+    {
+        let stdin = &mut self.stdin;
+        let reason = match ended.reason
+        {
+            GameEndReason::TurnsExhausted => "TURNS_EXHAUSTED",
+            GameEndReason::NoPlayersLeft => "NO_PLAYERS_LEFT",
+            GameEndReason::LastPlayerStanding => "LAST_PLAYER_STANDING",
+        };
+        writeln!(stdin, "GAMEOVER {} {} {}", ended.game_id, ended.standings.len(), reason)?;
+        for s in &ended.standings
+        {
+            writeln!(stdin, "{} {} {}", s.id, s.name, s.points)?;
+        }
+        stdin.flush()
+    }
+
+    /// Reads the guest's answer to an overview; `None` means the guest asked to quit.
+    pub fn read_command(&mut self) -> Result<Option<ClientCommand>, String>
+    {
+        let cli = self.read_line().map_err(|why| format!("{}", why))?;
+        match cli.trim()
+        {
+            "NOTHING" => Ok(Some(ClientCommand::Nothing)),
+            "UP" => Ok(Some(ClientCommand::Move(Direction::Up))),
+            "DOWN" => Ok(Some(ClientCommand::Move(Direction::Down))),
+            "LEFT" => Ok(Some(ClientCommand::Move(Direction::Left))),
+            "RIGHT" => Ok(Some(ClientCommand::Move(Direction::Right))),
+            "QUIT" => Ok(None),
+            cmd => Err(format!("Invalid command: {}", cmd)),
+        }
+    }
+}
+
+/* INPUT FORMAT
+N: number of players
+R, C: rows and columns of the grid
+T: number of tokens
+E: turns left
+M: milliseconds to make the move
+positionals arguments
+ID: player id
+S: player name (string)
+P: points
+X, Y: position
+A: 1 if the player is still in the game, 0 once eliminated
+F: team of the player (string), - when it plays on its own
+H: length of the trail of the player, followed by the X Y of each of its cells
+V: points paid per cell by a token
+L: ticks before a token or a power-up disappears, -1 if it never does
+U: number of power-ups on the board
+K: number of power-ups in effect
+B: power-up kind, one of SPEED, SHIELD or BOMB
+Q: number of teams, 0 when everyone plays on its own
+O: the number the cells of a team have in the grid
+D: number of cells of a team
+
+INPUT:
+N R C T E M
+N lines containing: ID S P X Y A F H, then H pairs of X Y
+R lines, each containing C numbers: -1 for nothing, -2 for a wall, the owner otherwise:
+the player id, or in team play the lowest id of the team
+T lines: X Y V L
+U K
+U lines: X Y B L
+K lines: ID B L, L being the ticks the power-up of the player still lasts
+Q
+Q lines: F O P D, P being the sum of the points of the players of the team
+
+The guest must answer each of these blocks with one of NOTHING, UP, DOWN,
+LEFT, RIGHT or QUIT. The last overview of a game (E = 0) is not sent;
+when a game ends the guest receives instead, without answering:
+
+GAMEOVER G N W
+N lines containing: ID S P, from the first to the last ranked player
+
+G: game id
+W: why the game ended, one of TURNS_EXHAUSTED, NO_PLAYERS_LEFT or LAST_PLAYER_STANDING
+*/
@@ -0,0 +1,42 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate twox_hash;
+extern crate tungstenite;
+extern crate serde;
+extern crate rand;
+extern crate url;
+extern crate sha1;
+
+pub mod arena;
+pub mod auth;
+pub mod bot;
+pub mod chasher;
+pub mod common;
+pub mod game;
+pub mod grid;
+pub mod guest;
+pub mod map;
+pub mod powerup;
+pub mod replay;
+pub mod room;
+pub mod rules;
+pub mod server;
+pub mod spawn;
+pub mod team;
+
+pub use bot::{Bot, Runner};
+pub use common::{ClientCommand, ClientMessage, ClientRole, Overview, ServerMessage, ServerResponse};
+pub use game::{Game, GameConfig};
+pub use server::{Server, ServerConfig};
@@ -0,0 +1,187 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::Point;
+use game::BoardConfig;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+/// A board drawn in a text file, one line per row, one character per cell:
+/// - `.` a free cell
+/// - `#` a wall
+/// - `S` a spawn point
+/// - `*` a token placed at the start of the game
+/// - `+` a cell where tokens can appear; if there is none they appear anywhere
+#[derive(Debug, Clone)]
+pub struct Map
+{
+    pub name: String,
+    pub rows: usize,
+    pub cols: usize,
+    pub walls: Vec<Point>,
+    pub spawns: Vec<Point>,
+    pub tokens: Vec<Point>,
+    pub token_zones: Vec<Point>,
+}
+
+impl Map
+{
+    pub fn parse(name: &str, text: &str) -> Result<Map, String>
+    {
+        let mut lines: Vec<&str> = text.lines().map(|l| l.trim_end()).skip_while(|l| l.is_empty()).collect();
+        while lines.last() == Some(&"")
+        {
+            lines.pop();
+        }
+        if lines.is_empty()
+        {
+            return Err(String::from("Empty map"));
+        }
+        let cols = lines[0].chars().count();
+        let mut walls: Vec<Point> = Vec::new();
+        let mut spawns: Vec<Point> = Vec::new();
+        let mut tokens: Vec<Point> = Vec::new();
+        let mut token_zones: Vec<Point> = Vec::new();
+        for (y, line) in lines.iter().enumerate()
+        {
+            if line.is_empty()
+            {
+                return Err(format!("Row {} is empty", y));
+            }
+            if line.chars().count() != cols
+            {
+                return Err(format!("Row {} is {} cells long, expected {}", y, line.chars().count(), cols));
+            }
+            for (x, c) in line.chars().enumerate()
+            {
+                match c
+                {
+                    '#' => walls.push(Point { x, y }),
+                    'S' => spawns.push(Point { x, y }),
+                    '*' => tokens.push(Point { x, y }),
+                    '+' => token_zones.push(Point { x, y }),
+                    '.' => (),
+                    _ => return Err(format!("Invalid cell '{}' at row {}, column {}", c, y, x)),
+                }
+            }
+        }
+        Ok(Map {
+               name: String::from(name),
+               rows: lines.len(),
+               cols,
+               walls,
+               spawns,
+               tokens,
+               token_zones,
+           })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Map, String>
+    {
+        let mut text = String::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|why| format!("Cannot read map {}: {}", path.as_ref().display(), why))?;
+        let name = path.as_ref()
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Map::parse(&name, &text).map_err(|why| format!("Invalid map {}: {}", path.as_ref().display(), why))
+    }
+
+    /// Loads every `.txt` map of a directory, sorted by file name; other files are left alone
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<Map>, String>
+    {
+        let entries = fs::read_dir(&dir).map_err(|why| format!("Cannot read maps directory {}: {}", dir.as_ref().display(), why))?;
+        let mut paths: Vec<_> = entries.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && !p.file_name().unwrap().to_string_lossy().starts_with('.'))
+            .filter(|p| p.extension().map(|e| e == "txt").unwrap_or(false))
+            .collect();
+        paths.sort();
+        let mut maps: Vec<Map> = Vec::new();
+        for path in paths
+        {
+            maps.push(Map::load(path)?);
+        }
+        if maps.is_empty()
+        {
+            return Err(format!("No maps in {}", dir.as_ref().display()));
+        }
+        Ok(maps)
+    }
+
+    pub fn apply(&self, board: &mut BoardConfig)
+    {
+        board.rows = Some(self.rows);
+        board.cols = Some(self.cols);
+        board.walls = self.walls.clone();
+        board.spawns = self.spawns.clone();
+        board.tokens = self.tokens.clone();
+        board.token_zones = self.token_zones.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn parses_every_symbol()
+    {
+        let map = Map::parse("test", "\nS.#\n*+.\n..S\n\n").unwrap();
+        assert_eq!((map.rows, map.cols), (3, 3));
+        assert_eq!(map.walls, vec![Point { x: 2, y: 0 }]);
+        assert_eq!(map.spawns, vec![Point { x: 0, y: 0 }, Point { x: 2, y: 2 }]);
+        assert_eq!(map.tokens, vec![Point { x: 0, y: 1 }]);
+        assert_eq!(map.token_zones, vec![Point { x: 1, y: 1 }]);
+        let mut board: BoardConfig = Default::default();
+        map.apply(&mut board);
+        assert_eq!((board.rows, board.cols), (Some(3), Some(3)));
+        assert_eq!(board.spawns, map.spawns);
+    }
+
+    #[test]
+    fn map_without_spawns_is_valid()
+    {
+        let map = Map::parse("open", "...\n.#.\n").unwrap();
+        assert!(map.spawns.is_empty());
+        let mut board: BoardConfig = Default::default();
+        board.spawns.push(Point { x: 0, y: 0 });
+        map.apply(&mut board);
+        assert!(board.spawns.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_maps()
+    {
+        assert!(Map::parse("empty", "\n\n").is_err());
+        assert!(Map::parse("ragged", "...\n..\n").unwrap_err().contains("Row 1"));
+        assert!(Map::parse("gap", "...\n\n...\n").unwrap_err().contains("Row 1 is empty"));
+        assert!(Map::parse("unknown", "..\n.x\n").unwrap_err().contains("'x'"));
+    }
+
+    #[test]
+    fn load_dir_skips_files_that_are_not_maps()
+    {
+        let dir = env::temp_dir().join(format!("dazzle-maps-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("duel.txt"), "S.S\n").unwrap();
+        fs::write(dir.join("README.md"), "# Maps for the duel room\n").unwrap();
+        fs::write(dir.join(".duel.txt.swp"), "garbage").unwrap();
+        let maps = Map::load_dir(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let names: Vec<String> = maps.unwrap().into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec![String::from("duel")]);
+    }
+}
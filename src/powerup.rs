@@ -0,0 +1,131 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use game::Game;
+use rand::Rng;
+use rand::distributions::{IndependentSample, Range};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PowerUpConfig
+{
+    /// Chance of a new power-up each tick, none appear when zero
+    pub rate: f64,
+    /// Kinds that can appear, each one with the same chance
+    pub kinds: Vec<PowerUpKind>,
+    /// Ticks a power-up stays on the board, forever if unset
+    pub ttl: Option<u64>,
+    pub speed_ticks: u64,
+    pub shield_ticks: u64,
+}
+
+impl Default for PowerUpConfig
+{
+    fn default() -> PowerUpConfig
+    {
+        PowerUpConfig {
+            rate: 0.0,
+            kinds: vec![PowerUpKind::Speed, PowerUpKind::Shield, PowerUpKind::Bomb],
+            ttl: None,
+            speed_ticks: 5,
+            shield_ticks: 10,
+        }
+    }
+}
+
+impl PowerUpKind
+{
+    pub fn by_name(name: &str) -> Option<PowerUpKind>
+    {
+        match name
+        {
+            "speed" => Some(PowerUpKind::Speed),
+            "shield" => Some(PowerUpKind::Shield),
+            "bomb" => Some(PowerUpKind::Bomb),
+            _ => None,
+        }
+    }
+}
+
+pub fn spawn(game: &mut Game)
+{
+    if game.config.power_ups.rate <= 0.0 || game.config.power_ups.kinds.is_empty() ||
+       game.rng.next_f64() >= game.config.power_ups.rate
+    {
+        return;
+    }
+    let cell = game.pickup_cell();
+    if !game.can_drop(&cell)
+    {
+        return;
+    }
+    let kinds = &game.config.power_ups.kinds;
+    let kind = kinds[Range::new(0, kinds.len()).ind_sample(&mut game.rng)];
+    let power_up = PowerUp {
+        position: cell.clone(),
+        kind,
+        ttl: game.config.power_ups.ttl.map(|t| t.max(1)),
+    };
+    game.power_ups.insert(cell, power_up);
+}
+
+/// Hands the power-ups under the active players to them
+pub fn pick_up(game: &mut Game)
+{
+    let picked: Vec<(u64, PowerUp)> = game.players
+        .values()
+        .filter(|p| p.active)
+        .filter_map(|p| game.power_ups.get(&p.position).map(|u| (p.id, u.clone())))
+        .collect();
+    for (id, power_up) in picked
+    {
+        game.power_ups.remove(&power_up.position);
+        let ticks = match power_up.kind
+        {
+            PowerUpKind::Speed => game.config.power_ups.speed_ticks,
+            PowerUpKind::Shield => game.config.power_ups.shield_ticks,
+            PowerUpKind::Bomb =>
+            {
+                bomb(game, id, &power_up.position);
+                continue;
+            }
+        };
+        let end = game.tick + 1 + ticks;
+        game.grant_effect(id, power_up.kind, end);
+    }
+}
+
+/// Clears the unshielded opponents' cells of the 3x3 area around `center`
+fn bomb(game: &mut Game, id: u64, center: &Point)
+{
+    let color = game.color(id);
+    for y in center.y.saturating_sub(1)..(center.y + 2).min(game.grid.rows())
+    {
+        for x in center.x.saturating_sub(1)..(center.x + 2).min(game.grid.cols())
+        {
+            match game.grid.get(x, y)
+            {
+                Some(owner) if owner != color && !game.shielded(owner) => game.grid.set(x, y, None),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Ages the power-ups on the board and drops the effects that ran out
+pub fn expire(game: &mut Game)
+{
+    for power_up in game.power_ups.values_mut()
+    {
+        power_up.ttl = power_up.ttl.map(|t| t - 1);
+    }
+    game.power_ups.retain(|_, u| u.ttl != Some(0));
+    game.expire_effects();
+}
@@ -0,0 +1,207 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use game::{Game, GameConfig};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ReplayEvent
+{
+    Command(u64, ClientCommand),
+    Leave(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReplayFrame
+{
+    pub events: Vec<ReplayEvent>,
+    pub overview: Overview,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Replay
+{
+    pub game_id: u64,
+    pub config: GameConfig,
+    pub players: Vec<Player>,
+    pub frames: Vec<ReplayFrame>,
+    #[serde(skip)]
+    pending: Vec<ReplayEvent>,
+}
+
+impl Replay
+{
+    pub fn new(game_id: u64, config: GameConfig, players: &BTreeMap<u64, Player>) -> Replay
+    {
+        Replay {
+            game_id,
+            config,
+            players: players.values().cloned().collect(),
+            frames: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, String>
+    {
+        let f = File::open(path).map_err(|why| format!("Cannot open replay: {}", why))?;
+        serde_json::from_reader(BufReader::new(f)).map_err(|why| format!("Invalid replay: {}", why))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<PathBuf, String>
+    {
+        fs::create_dir_all(&dir).map_err(|why| format!("Cannot create replay directory: {}", why))?;
+        let name = format!("game-{}-{}", self.game_id, self.config.seed);
+        // Game ids restart with the server, so never overwrite the replay of an earlier run
+        for n in 0..
+        {
+            let path = match n
+            {
+                0 => dir.as_ref().join(format!("{}.json", name)),
+                _ => dir.as_ref().join(format!("{}-{}.json", name, n)),
+            };
+            let f = match OpenOptions::new().write(true).create_new(true).open(&path)
+            {
+                Ok(f) => f,
+                Err(ref why) if why.kind() == ErrorKind::AlreadyExists => continue,
+                Err(why) => return Err(format!("Cannot create replay: {}", why)),
+            };
+            serde_json::to_writer(BufWriter::new(f), self).map_err(|why| format!("Cannot write replay: {}", why))?;
+            return Ok(path);
+        }
+        unreachable!()
+    }
+
+    pub fn record(&mut self, event: ReplayEvent)
+    {
+        self.pending.push(event);
+    }
+
+    pub fn push_frame(&mut self, overview: Overview)
+    {
+        let events = self.pending.drain(..).collect();
+        self.frames.push(ReplayFrame { events, overview });
+    }
+
+    pub fn verify(&self) -> Result<(), String>
+    {
+        let mut players: BTreeMap<u64, Player> = Default::default();
+        for player in &self.players
+        {
+            players.insert(player.id, player.clone());
+        }
+        let mut game = Game::new(players, self.game_id, self.config.clone())?;
+        for (n, frame) in self.frames.iter().enumerate()
+        {
+            for event in &frame.events
+            {
+                match *event
+                {
+                    ReplayEvent::Command(id, ref command) =>
+                    {
+                        if let Err(why) = game.action(id, command.clone())
+                        {
+                            return Err(format!("Frame {}: recorded command of player {} rejected: {}", n, id, why));
+                        }
+                    }
+                    ReplayEvent::Leave(id) =>
+                    {
+                        game.leave(id);
+                    }
+                }
+            }
+            game.tick();
+            let mut overview = game.overview(frame.overview.ms_for_turn);
+            overview.deadline_ms = frame.overview.deadline_ms;
+            if overview != frame.overview
+            {
+                return Err(format!("Frame {}: simulation diverges from the recorded overview", n));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Records a short game of two players zigzagging down and right
+    fn recorded() -> Replay
+    {
+        let config = GameConfig {
+            turns: 10,
+            seed: 3,
+            ..Default::default()
+        };
+        let players: BTreeMap<u64, Player> = (1..3).map(|id| (id, Player::new(id, format!("p{}", id)))).collect();
+        let mut replay = Replay::new(0, config.clone(), &players);
+        let mut game = Game::new(players, 0, config).unwrap();
+        game.tick();
+        replay.push_frame(game.overview(100));
+        while !game.finished()
+        {
+            for id in 1..3
+            {
+                let direction = if game.tick & 1 == 0 { Direction::Right } else { Direction::Down };
+                let command = ClientCommand::Move(direction);
+                if game.action(id, command.clone()).is_ok()
+                {
+                    replay.record(ReplayEvent::Command(id, command));
+                }
+            }
+            game.tick();
+            replay.push_frame(game.overview(100));
+        }
+        replay
+    }
+
+    #[test]
+    fn recorded_game_verifies()
+    {
+        assert_eq!(recorded().verify(), Ok(()));
+    }
+
+    #[test]
+    fn changed_command_diverges()
+    {
+        let mut replay = recorded();
+        let event = replay.frames.iter_mut().flat_map(|f| f.events.iter_mut()).next().unwrap();
+        *event = ReplayEvent::Command(1, ClientCommand::Nothing);
+        assert_eq!(replay.verify(), Err(String::from("Frame 1: simulation diverges from the recorded overview")));
+    }
+
+    #[test]
+    fn changed_overview_diverges()
+    {
+        let mut replay = recorded();
+        replay.frames[3].overview.players[0].points += 1;
+        assert_eq!(replay.verify(), Err(String::from("Frame 3: simulation diverges from the recorded overview")));
+    }
+
+    #[test]
+    fn saving_twice_keeps_both_replays()
+    {
+        let dir = env::temp_dir().join(format!("dazzle-replays-{}", process::id()));
+        let replay = Replay::new(0, Default::default(), &Default::default());
+        let first = replay.save(&dir).unwrap();
+        let second = replay.save(&dir).unwrap();
+        assert_ne!(first, second);
+        assert!(first.exists() && second.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
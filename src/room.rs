@@ -0,0 +1,254 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use game::{BoardConfig, Game, GhostPolicy, TokenConfig};
+use map::Map;
+use powerup::PowerUpConfig;
+use replay::Replay;
+use rules::DEFAULT_RULES;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+pub const DEFAULT_ROOM: &str = "default";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RoomConfig
+{
+    pub game_start_ticks: u64,
+    pub game_turns: u64,
+    pub token_rate: f64,
+    pub min_players: usize,
+    pub max_players: Option<usize>,
+    pub ghost_policy: GhostPolicy,
+    pub rules: String,
+    pub board: BoardConfig,
+    pub tokens: TokenConfig,
+    pub power_ups: PowerUpConfig,
+    /// Number of teams the players are split into, zero for everyone on their own
+    pub teams: usize,
+    pub map_file: Option<String>,
+    pub maps_dir: Option<String>,
+}
+
+pub struct RunningGame
+{
+    pub game: Game,
+    pub replay: Replay,
+    pub deadline: Instant,
+}
+
+pub enum Countdown
+{
+    Idle,
+    Waiting(u64),
+    Start(Vec<Vec<Player>>),
+}
+
+pub struct Room
+{
+    pub name: String,
+    pub config: RoomConfig,
+    pub queue: Vec<Player>,
+    pub ticks_left: u64,
+    pub games: BTreeMap<u64, RunningGame>,
+    pub maps: Vec<Map>,
+    pub next_map: usize,
+}
+
+impl Default for RoomConfig
+{
+    fn default() -> RoomConfig
+    {
+        RoomConfig {
+            game_start_ticks: 60,
+            game_turns: 300,
+            token_rate: 2.5,
+            min_players: 2,
+            max_players: None,
+            ghost_policy: GhostPolicy::Freeze,
+            rules: String::from(DEFAULT_RULES),
+            board: Default::default(),
+            tokens: Default::default(),
+            power_ups: Default::default(),
+            teams: 0,
+            map_file: None,
+            maps_dir: None,
+        }
+    }
+}
+
+impl Room
+{
+    pub fn new(name: &str, config: RoomConfig) -> Room
+    {
+        let mut config = config;
+        config.min_players = config.min_players.max(1);
+        Room {
+            name: String::from(name),
+            ticks_left: config.game_start_ticks,
+            config,
+            queue: Vec::new(),
+            games: Default::default(),
+            maps: Vec::new(),
+            next_map: 0,
+        }
+    }
+
+    /// Loads the maps of the room, a directory takes precedence over a single map
+    pub fn load_maps(&mut self) -> Result<(), String>
+    {
+        if let Some(ref dir) = self.config.maps_dir
+        {
+            self.maps = Map::load_dir(dir)?;
+        }
+        else if let Some(ref path) = self.config.map_file
+        {
+            self.maps = vec![Map::load(path)?];
+        }
+        Ok(())
+    }
+
+    /// Board of the next game, rotating through the maps of the room
+    pub fn next_board(&mut self) -> (BoardConfig, Option<String>)
+    {
+        let mut board = self.config.board.clone();
+        if self.maps.is_empty()
+        {
+            return (board, None);
+        }
+        let map = &self.maps[self.next_map % self.maps.len()];
+        self.next_map += 1;
+        map.apply(&mut board);
+        (board, Some(map.name.clone()))
+    }
+
+    pub fn is_queued(&self, id: u64) -> bool
+    {
+        self.queue.iter().any(|p| p.id == id)
+    }
+
+    pub fn game_of(&self, id: u64) -> Option<u64>
+    {
+        self.games
+            .iter()
+            .find(|&(_, running)| running.game.players.contains_key(&id))
+            .map(|(game_id, _)| *game_id)
+    }
+
+    /// Queues a player; the countdown starts when the queue reaches the minimum
+    /// size and later joins do not restart it
+    pub fn enqueue(&mut self, player: Player)
+    {
+        self.queue.push(player);
+        if self.queue.len() == self.config.min_players
+        {
+            self.ticks_left = self.config.game_start_ticks;
+        }
+    }
+
+    pub fn match_sizes(&self, queued: usize) -> Vec<usize>
+    {
+        let max_players = self.config.max_players.unwrap_or(queued).max(self.config.min_players);
+        let mut matches = queued.div_ceil(max_players);
+        while matches > 1 && queued / matches < self.config.min_players
+        {
+            matches -= 1;
+        }
+        let playing = queued.min(matches * max_players);
+        (0..matches).map(|i| playing / matches + if i < playing % matches { 1 } else { 0 }).collect()
+    }
+
+    pub fn countdown(&mut self) -> Countdown
+    {
+        if self.queue.len() < self.config.min_players
+        {
+            Countdown::Idle
+        }
+        else if self.ticks_left > 0
+        {
+            self.ticks_left -= 1;
+            Countdown::Waiting(self.ticks_left + 1)
+        }
+        else
+        {
+            let mut matches: Vec<Vec<Player>> = Vec::new();
+            for size in self.match_sizes(self.queue.len())
+            {
+                matches.push(self.queue.drain(..size).collect());
+            }
+            self.ticks_left = self.config.game_start_ticks;
+            Countdown::Start(matches)
+        }
+    }
+
+    pub fn lobby_status(&self, tick_time: u64) -> LobbyStatus
+    {
+        let mut queued: Vec<QueuedPlayer> = self.queue
+            .iter()
+            .map(|p| {
+                     QueuedPlayer {
+                         id: p.id,
+                         name: p.name.clone(),
+                     }
+                 })
+            .collect();
+        queued.sort_by(|a, b| a.name.cmp(&b.name));
+        let ticks_left = if queued.len() >= self.config.min_players
+        {
+            Some(self.ticks_left)
+        }
+        else
+        {
+            None
+        };
+        LobbyStatus {
+            room: self.name.clone(),
+            queued,
+            ticks_left,
+            ms_left: ticks_left.map(|t| t * tick_time),
+            min_players: self.config.min_players as u64,
+            max_players: self.config.max_players.map(|m| m as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn player(id: u64) -> Player
+    {
+        Player::new(id, format!("p{}", id))
+    }
+
+    #[test]
+    fn late_joins_do_not_restart_the_countdown()
+    {
+        let config = RoomConfig {
+            game_start_ticks: 3,
+            ..Default::default()
+        };
+        let mut room = Room::new("test", config);
+        room.enqueue(player(1));
+        assert!(matches!(room.countdown(), Countdown::Idle));
+        room.enqueue(player(2));
+        assert!(matches!(room.countdown(), Countdown::Waiting(3)));
+        assert!(matches!(room.countdown(), Countdown::Waiting(2)));
+        room.enqueue(player(3));
+        assert!(matches!(room.countdown(), Countdown::Waiting(1)));
+        match room.countdown()
+        {
+            Countdown::Start(matches) => assert_eq!(matches.iter().map(|m| m.len()).sum::<usize>(), 3),
+            _ => panic!("the game did not start"),
+        }
+    }
+}
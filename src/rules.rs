@@ -0,0 +1,560 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use game::Game;
+use std::collections::{BTreeSet, VecDeque};
+use std::mem;
+
+pub const DEFAULT_RULES: &str = "classic";
+
+/// The phases of a tick that follow the movement of the players.
+/// Every phase defaults to the classic game, a variant overrides only what it changes.
+pub trait Rules: Sync
+{
+    fn name(&self) -> &'static str;
+
+    /// Marks the cells the players are standing on
+    fn paint(&self, game: &mut Game)
+    {
+        let stepping: Vec<(u64, Point)> = game.players
+            .values()
+            .filter(|p| p.active)
+            .map(|p| (p.id, p.position.clone()))
+            .collect();
+        for (id, position) in stepping
+        {
+            game.claim(position.x, position.y, id);
+        }
+    }
+
+    /// Gives to a player every empty area that only its cells enclose
+    fn capture(&self, game: &mut Game)
+    {
+        game.grid.capture();
+    }
+
+    /// Tells which cells of a player are turned into points by a token
+    fn cashable(&self, game: &Game, id: u64, x: usize, y: usize) -> bool
+    {
+        game.grid.get(x, y) == Some(game.color(id))
+    }
+
+    /// Turns the cells of the players standing on a token into points.
+    /// In team play the whole territory of the team is cashed in, the points going
+    /// to the player on the token and so to the pool of its team.
+    fn cash_in(&self, game: &mut Game)
+    {
+        let ids: Vec<u64> = game.players
+            .values()
+            .filter(|p| p.active && game.tokens.contains_key(&p.position))
+            .map(|p| p.id)
+            .collect();
+        for id in ids
+        {
+            let position = game.players[&id].position.clone();
+            let value = game.tokens.remove(&position).map(|t| t.value).unwrap_or(1);
+            let cells: Vec<usize> = game.grid
+                .cells_of(game.color(id))
+                .iter()
+                .cloned()
+                .filter(|&i| {
+                            let p = game.grid.point(i);
+                            self.cashable(game, id, p.x, p.y)
+                        })
+                .collect();
+            for &i in &cells
+            {
+                game.grid.set_at(i, None);
+            }
+            game.players.get_mut(&id).unwrap().points += cells.len() as u64 * value;
+        }
+    }
+}
+
+pub struct Classic;
+
+impl Rules for Classic
+{
+    fn name(&self) -> &'static str
+    {
+        "classic"
+    }
+}
+
+/// Tokens only pay for the inner cells of a territory, its outline stays
+pub struct EnclosedCashIn;
+
+impl Rules for EnclosedCashIn
+{
+    fn name(&self) -> &'static str
+    {
+        "enclosed-cash-in"
+    }
+
+    fn cashable(&self, game: &Game, id: u64, x: usize, y: usize) -> bool
+    {
+        let color = game.color(id);
+        let owned = |x: usize, y: usize| x < game.grid.cols() && y < game.grid.rows() && game.grid.get(x, y) == Some(color);
+        owned(x, y) && x > 0 && y > 0 && owned(x - 1, y) && owned(x + 1, y) && owned(x, y - 1) && owned(x, y + 1)
+    }
+}
+
+/// Stepping on a cell of an opponent steals the whole connected part of its territory
+pub struct StealTrail;
+
+impl Rules for StealTrail
+{
+    fn name(&self) -> &'static str
+    {
+        "steal-trail"
+    }
+
+    fn paint(&self, game: &mut Game)
+    {
+        let cols = game.grid.cols();
+        let rows = game.grid.rows();
+        let stepping: Vec<(u64, Point)> = game.players
+            .values()
+            .filter(|p| p.active)
+            .map(|p| (p.id, p.position.clone()))
+            .collect();
+        for (id, position) in stepping
+        {
+            let color = game.color(id);
+            let victim = match game.grid.get(position.x, position.y)
+            {
+                Some(other) if other != color && !game.shielded(other) => other,
+                _ => continue,
+            };
+            let mut queue: VecDeque<Point> = Default::default();
+            queue.push_back(position);
+            while let Some(p) = queue.pop_front()
+            {
+                if game.grid.get(p.x, p.y) != Some(victim)
+                {
+                    continue;
+                }
+                game.grid.set(p.x, p.y, Some(color));
+                if p.x + 1 < cols
+                {
+                    queue.push_back(Point { x: p.x + 1, y: p.y });
+                }
+                if p.x > 0
+                {
+                    queue.push_back(Point { x: p.x - 1, y: p.y });
+                }
+                if p.y + 1 < rows
+                {
+                    queue.push_back(Point { x: p.x, y: p.y + 1 });
+                }
+                if p.y > 0
+                {
+                    queue.push_back(Point { x: p.x, y: p.y - 1 });
+                }
+            }
+        }
+        Classic.paint(game);
+    }
+}
+
+/// Closing a loop also takes the enemy cells inside it:
+/// - every area that only the cells of an active player enclose goes to it,
+///   enemy cells included; an area inside several loops goes to the innermost one
+///   (the smallest area, then the lowest color);
+/// - an empty area enclosed by several colors goes to the active color owning most
+///   of its border, and stays empty on a tie;
+/// - the cells of a shielded player are never taken.
+pub struct EnemyCapture;
+
+impl Rules for EnemyCapture
+{
+    fn name(&self) -> &'static str
+    {
+        "enemy-capture"
+    }
+
+    fn capture(&self, game: &mut Game)
+    {
+        let colors: Vec<u64> = game.players
+            .values()
+            .filter(|p| p.active)
+            .map(|p| game.color(p.id))
+            .collect::<BTreeSet<u64>>()
+            .into_iter()
+            .collect();
+        for (i, id) in game.grid.enclosures(&colors)
+        {
+            let p = game.grid.point(i);
+            game.claim(p.x, p.y, id);
+        }
+        game.grid.fill_pockets(&colors);
+    }
+}
+
+/// In the style of Paper.io: outside of its territory a player leaves a trail, that
+/// becomes territory, enemy cells included, once the player gets back home.
+/// A player is eliminated, losing its territory, when another one steps on its trail
+/// before it is closed, or when all its territory has been taken.
+pub struct Elimination;
+
+impl Rules for Elimination
+{
+    fn name(&self) -> &'static str
+    {
+        "elimination"
+    }
+
+    fn paint(&self, game: &mut Game)
+    {
+        let stepping: Vec<(u64, Point)> = game.players
+            .values()
+            .filter(|p| p.active)
+            .map(|p| (p.id, p.position.clone()))
+            .collect();
+        let out: Vec<u64> = game.players
+            .values()
+            .filter(|p| p.alive)
+            .filter(|p| {
+                        stepping.iter().any(|&(id, ref position)| {
+                                                game.color(id) != game.color(p.id) && p.trail.contains(position)
+                                            }) ||
+                        (game.tick > 0 && game.grid.count(game.color(p.id)) == 0)
+                    })
+            .map(|p| p.id)
+            .collect();
+        for &id in &out
+        {
+            game.eliminate(id);
+        }
+        for (id, position) in stepping.into_iter().filter(|&(id, _)| !out.contains(&id))
+        {
+            if game.tick == 0 || game.grid.get(position.x, position.y) == Some(game.color(id))
+            {
+                // Shielded cells stay in the trail until the shield wears off
+                let trail: Vec<Point> = mem::take(&mut game.players.get_mut(&id).unwrap().trail)
+                    .into_iter()
+                    .filter(|cell| !game.claim(cell.x, cell.y, id))
+                    .collect();
+                game.players.get_mut(&id).unwrap().trail = trail;
+                game.claim(position.x, position.y, id);
+                continue;
+            }
+            let trail = &mut game.players.get_mut(&id).unwrap().trail;
+            if !trail.contains(&position)
+            {
+                trail.push(position);
+            }
+        }
+    }
+
+    fn capture(&self, game: &mut Game)
+    {
+        EnemyCapture.capture(game);
+    }
+}
+
+pub fn by_name(name: &str) -> Option<&'static dyn Rules>
+{
+    match name
+    {
+        "classic" => Some(&Classic),
+        "enclosed-cash-in" => Some(&EnclosedCashIn),
+        "steal-trail" => Some(&StealTrail),
+        "enemy-capture" => Some(&EnemyCapture),
+        "elimination" => Some(&Elimination),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use game::test_game;
+    use std::collections::BTreeMap;
+
+    /// A game on a board drawn with `.` for empty cells, `#` for walls and one lowercase
+    /// letter per player, `a` being the player with id 1, standing at `positions`
+    fn drawn(rules: &str, rows: &[&str], positions: &[(usize, usize)]) -> Game
+    {
+        let mut players: BTreeMap<u64, Player> = Default::default();
+        for c in rows.iter().flat_map(|row| row.chars()).filter(|c| c.is_ascii_lowercase())
+        {
+            let id = c as u64 - 'a' as u64 + 1;
+            players.insert(id, Player::new(id, format!("p{}", id)));
+        }
+        let mut game = test_game(players, Some(rows.len()), Some(rows[0].len()), positions);
+        game.config.rules = String::from(rules);
+        game.rules = by_name(rules).unwrap();
+        for (y, row) in rows.iter().enumerate()
+        {
+            for (x, c) in row.chars().enumerate()
+            {
+                match c
+                {
+                    '#' => game.grid.set_wall(x, y, true),
+                    'a'..='z' => game.grid.set(x, y, Some(c as u64 - 'a' as u64 + 1)),
+                    _ => (),
+                }
+            }
+        }
+        game
+    }
+    fn symbol(game: &Game, x: usize, y: usize) -> char
+    {
+        if game.grid.is_wall(x, y)
+        {
+            return '#';
+        }
+        game.grid.get(x, y).map(|id| (b'a' + id as u8 - 1) as char).unwrap_or('.')
+    }
+
+    fn drawing(game: &Game) -> Vec<String>
+    {
+        (0..game.grid.rows()).map(|y| (0..game.grid.cols()).map(|x| symbol(game, x, y)).collect()).collect()
+    }
+
+    fn captured(rows: &[&str]) -> Vec<String>
+    {
+        let mut game = drawn("enemy-capture", rows, &[]);
+        EnemyCapture.capture(&mut game);
+        drawing(&game)
+    }
+
+    #[test]
+    fn loop_takes_enemy_cells()
+    {
+        assert_eq!(captured(&[".......",
+                              ".aaaaa.",
+                              ".abb.a.",
+                              ".a.b.a.",
+                              ".aaaaa.",
+                              "......."]),
+                   vec![".......",
+                        ".aaaaa.",
+                        ".aaaaa.",
+                        ".aaaaa.",
+                        ".aaaaa.",
+                        "......."]);
+    }
+
+    #[test]
+    fn innermost_loop_wins()
+    {
+        assert_eq!(captured(&[".........",
+                              ".aaaaaaa.",
+                              ".a.....a.",
+                              ".a.bbb.a.",
+                              ".a.b.b.a.",
+                              ".a.bbb.a.",
+                              ".a.....a.",
+                              ".aaaaaaa.",
+                              "........."]),
+                   vec![".........",
+                        ".aaaaaaa.",
+                        ".aaaaaaa.",
+                        ".aaaaaaa.",
+                        ".aaabaaa.",
+                        ".aaaaaaa.",
+                        ".aaaaaaa.",
+                        ".aaaaaaa.",
+                        "........."]);
+    }
+
+    #[test]
+    fn loop_open_to_the_edge_takes_nothing()
+    {
+        let board = ["aaaa", "abba", "abb.", "aaaa"];
+        assert_eq!(captured(&board), board.to_vec());
+    }
+
+    #[test]
+    fn walls_close_loops()
+    {
+        assert_eq!(captured(&["......",
+                              ".aaa#.",
+                              ".ab.#.",
+                              ".aaa#.",
+                              "......"]),
+                   vec!["......",
+                        ".aaa#.",
+                        ".aaa#.",
+                        ".aaa#.",
+                        "......"]);
+    }
+
+    #[test]
+    fn mixed_pocket_goes_to_the_larger_border()
+    {
+        assert_eq!(captured(&[".......",
+                              ".aaabb.",
+                              ".a...b.",
+                              ".aaabb.",
+                              "......."]),
+                   vec![".......",
+                        ".aaabb.",
+                        ".aaaab.",
+                        ".aaabb.",
+                        "......."]);
+    }
+
+    #[test]
+    fn mixed_pocket_tie_stays_empty()
+    {
+        let board = ["......", ".aabb.", ".a..b.", ".aabb.", "......"];
+        assert_eq!(captured(&board), board.to_vec());
+    }
+
+    #[test]
+    fn inactive_colors_do_not_count_in_a_mixed_pocket()
+    {
+        let mut game = drawn("enemy-capture", &["......", ".abbb.", ".a..b.", ".abbb.", "......"], &[]);
+        game.players.get_mut(&2).unwrap().active = false;
+        EnemyCapture.capture(&mut game);
+        assert_eq!(drawing(&game), vec!["......", ".abbb.", ".aaab.", ".abbb.", "......"]);
+    }
+
+    #[test]
+    fn shielded_cells_are_not_taken()
+    {
+        let mut game = drawn("enemy-capture", &[".......", ".aaaaa.", ".abb.a.", ".a.b.a.", ".aaaaa.", "......."], &[]);
+        game.grant_effect(2, PowerUpKind::Shield, 5);
+        EnemyCapture.capture(&mut game);
+        assert_eq!(drawing(&game),
+                   vec![".......", ".aaaaa.", ".abbaa.", ".aabaa.", ".aaaaa.", "......."]);
+    }
+
+    #[test]
+    fn enclosed_cash_in_pays_only_inner_cells()
+    {
+        let mut game = drawn("enclosed-cash-in", &["aaaa.", "aaaa.", "aaaa.", "....."], &[]);
+        game.players.get_mut(&1).unwrap().position = Point { x: 0, y: 0 };
+        game.tokens.insert(Point { x: 0, y: 0 },
+                           Token {
+                               position: Point { x: 0, y: 0 },
+                               value: 3,
+                               ttl: None,
+                           });
+        EnclosedCashIn.cash_in(&mut game);
+        assert_eq!(game.players[&1].points, 6);
+        assert!(game.tokens.is_empty());
+        assert_eq!(drawing(&game), vec!["aaaa.", "a..a.", "aaaa.", "....."]);
+    }
+
+    #[test]
+    fn stepping_on_an_enemy_steals_its_connected_cells()
+    {
+        let board = ["aa.bb", ".a..b", ".....", "bb..."];
+        let mut game = drawn("steal-trail", &board, &[(3, 0), (0, 3)]);
+        game.grant_effect(2, PowerUpKind::Shield, 1);
+        StealTrail.paint(&mut game);
+        assert_eq!(drawing(&game), board.to_vec());
+        game.tick += 1;
+        game.expire_effects();
+        StealTrail.paint(&mut game);
+        assert_eq!(drawing(&game), vec!["aa.aa", ".a..a", ".....", "bb..."]);
+    }
+
+    fn play(game: &mut Game, moves: &[(u64, Direction)])
+    {
+        for &(id, ref direction) in moves
+        {
+            game.action(id, ClientCommand::Move(direction.clone())).unwrap();
+        }
+        game.tick();
+    }
+
+    #[test]
+    fn trail_becomes_territory_back_home()
+    {
+        let mut game = drawn("elimination", &["aa....", "......", "......", "......", ".....b"], &[(1, 0), (5, 4)]);
+        game.tick();
+        play(&mut game, &[(1, Direction::Down)]);
+        play(&mut game, &[(1, Direction::Down)]);
+        play(&mut game, &[(1, Direction::Left)]);
+        assert_eq!(game.players[&1].trail.len(), 3);
+        assert_eq!(game.grid.count(1), 2);
+        play(&mut game, &[(1, Direction::Up)]);
+        play(&mut game, &[(1, Direction::Up)]);
+        assert!(game.players[&1].trail.is_empty());
+        assert_eq!(drawing(&game), vec!["aa....", "aa....", "aa....", "......", ".....b"]);
+    }
+
+    #[test]
+    fn crossing_a_trail_eliminates_its_owner()
+    {
+        let mut game = drawn("elimination", &["a.....", "......", "......", "......", ".....b"], &[(0, 0), (5, 4)]);
+        game.tick();
+        play(&mut game, &[(1, Direction::Right), (2, Direction::Up)]);
+        play(&mut game, &[(1, Direction::Right), (2, Direction::Up)]);
+        play(&mut game, &[(1, Direction::Down), (2, Direction::Left)]);
+        play(&mut game, &[(1, Direction::Down), (2, Direction::Left)]);
+        assert!(game.players[&1].alive && game.players[&2].alive);
+        play(&mut game, &[(2, Direction::Up)]);
+        play(&mut game, &[(2, Direction::Left)]);
+        assert_eq!(game.players[&2].position, Point { x: 2, y: 1 });
+        assert!(!game.players[&1].alive);
+        assert!(!game.players[&1].active);
+        assert!(game.players[&1].trail.is_empty());
+        assert_eq!(game.grid.count(1), 0);
+        assert!(game.players[&2].alive);
+        assert_eq!(game.end_reason(), Some(GameEndReason::LastPlayerStanding));
+    }
+
+    #[test]
+    fn eliminated_sped_player_does_not_take_its_second_step()
+    {
+        let mut game = drawn("elimination", &["a.....", "......", "......", "......", ".....b"], &[(0, 0), (5, 4)]);
+        game.tick();
+        play(&mut game, &[(1, Direction::Right), (2, Direction::Up)]);
+        play(&mut game, &[(1, Direction::Right), (2, Direction::Up)]);
+        play(&mut game, &[(1, Direction::Down), (2, Direction::Left)]);
+        play(&mut game, &[(1, Direction::Down), (2, Direction::Left)]);
+        play(&mut game, &[(2, Direction::Up)]);
+        let end = game.tick + 5;
+        game.grant_effect(1, PowerUpKind::Speed, end);
+        play(&mut game, &[(1, Direction::Down), (2, Direction::Left)]);
+        assert!(!game.players[&1].alive);
+        assert_eq!(game.players[&1].position, Point { x: 2, y: 3 });
+    }
+
+    #[test]
+    fn shielded_cells_stay_in_the_trail()
+    {
+        let mut game = drawn("elimination", &["aa....", ".b....", "......", "......", ".....b"], &[(1, 0), (5, 4)]);
+        game.tick();
+        let end = game.tick + 3;
+        game.grant_effect(2, PowerUpKind::Shield, end);
+        play(&mut game, &[(1, Direction::Down)]);
+        play(&mut game, &[(1, Direction::Up)]);
+        assert_eq!(game.grid.get(1, 1), Some(2));
+        assert_eq!(game.players[&1].trail, vec![Point { x: 1, y: 1 }]);
+        while game.tick <= end
+        {
+            play(&mut game, &[]);
+        }
+        assert_eq!(game.grid.get(1, 1), Some(1));
+        assert!(game.players[&1].trail.is_empty());
+    }
+
+    #[test]
+    fn losing_all_territory_while_out_eliminates()
+    {
+        let mut game = drawn("elimination", &["a.....", "......", "......", "......", ".....b"], &[(0, 0), (5, 4)]);
+        game.tick();
+        play(&mut game, &[(1, Direction::Right)]);
+        assert!(game.players[&1].alive);
+        game.grid.set(0, 0, Some(2));
+        play(&mut game, &[(1, Direction::Right)]);
+        assert!(!game.players[&1].alive);
+        assert!(game.players[&1].trail.is_empty());
+        assert_eq!(game.end_reason(), Some(GameEndReason::LastPlayerStanding));
+    }
+}
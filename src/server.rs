@@ -7,34 +7,59 @@
  *
  */
 
-use chasher::player_hash;
+use auth::Registry;
+use chasher::{game_seed, player_hash};
 use common::*;
-use game::Game;
-use serde::ser::Serialize;
+use game::{BoardConfig, Game, GameConfig, GhostPolicy, TokenConfig};
+use powerup::PowerUpConfig;
+use rand;
+use replay::{Replay, ReplayEvent};
+use room::{Countdown, DEFAULT_ROOM, Room, RoomConfig, RunningGame};
+use rules::{self, DEFAULT_RULES};
 use serde_json;
-use std::collections::{HashMap, HashSet};
-use std::io::ErrorKind;
-use std::net::{TcpListener, TcpStream};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
 use std::sync::mpsc::{Receiver, Sender, channel};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use team;
 use tungstenite::{Error, Message, WebSocket, accept};
 use tungstenite::protocol::Role;
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct ServerConfig
 {
-    tick_time_ms: u64,
-    server_port: u16,
-    game_start_ticks: u64,
-    game_turns: u64,
-    token_rate: f64,
+    pub tick_time_ms: u64,
+    pub server_port: u16,
+    pub game_start_ticks: u64,
+    pub game_turns: u64,
+    pub token_rate: f64,
+    pub seed: Option<u64>,
+    pub replay_dir: Option<String>,
+    pub min_players: usize,
+    pub max_players: Option<usize>,
+    /// Relative to the directory of the configuration file, like `map_file` and `maps_dir`
+    pub players_file: Option<String>,
+    pub reconnect_grace_ticks: u64,
+    pub ghost_policy: GhostPolicy,
+    pub rules: String,
+    pub board: BoardConfig,
+    pub tokens: TokenConfig,
+    pub power_ups: PowerUpConfig,
+    pub teams: usize,
+    pub map_file: Option<String>,
+    pub maps_dir: Option<String>,
+    /// Extra rooms; `default` is reserved for the room made of the settings above
+    pub rooms: BTreeMap<String, RoomConfig>,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 enum ConnectionType
 {
-    Viewer,
+    Viewer(Option<String>),
     Player(u64),
     Unknown,
 }
@@ -45,6 +70,14 @@ struct Connection
     socket: WebSocket<TcpStream>,
 }
 
+struct Session
+{
+    player_id: u64,
+    name: String,
+    room: String,
+    team: Option<String>,
+}
+
 enum MessageResponse
 {
     Mail(u64, Message),
@@ -53,19 +86,22 @@ enum MessageResponse
 
 pub struct Server
 {
+    local_addr: SocketAddr,
     connections: HashMap<u64, Connection>,
     incoming_connections: Receiver<WebSocket<TcpStream>>,
     incoming_messages: Receiver<MessageResponse>,
     message_sender: Sender<MessageResponse>,
-    queue: HashMap<u64, Player>,
     seed: u64,
     tick_time: u64,
+    next_tick: Instant,
     game_id: u64,
-    game_start_ticks: u64,
-    game_turns: u64,
-    game_start_ticks_left: u64,
-    game: Option<Game>,
-    token_rate: f64,
+    rooms: BTreeMap<String, Room>,
+    base_seed: u64,
+    replay_dir: Option<String>,
+    registry: Option<Registry>,
+    sessions: HashMap<String, Session>,
+    orphans: HashMap<u64, u64>,
+    reconnect_grace_ticks: u64,
 }
 
 impl Drop for Connection
@@ -78,7 +114,7 @@ impl Drop for Connection
 
 impl Connection
 {
-    #[cfg_attr(rustfmt, rustfmt_skip)]
+    #[rustfmt::skip]
     pub fn new(ws: WebSocket<TcpStream>, sender: Sender<MessageResponse>, seed: u64) -> Connection
     {
         let mut ws = ws;
@@ -106,10 +142,44 @@ impl Server
 {
     pub fn new(config: ServerConfig) -> Server
     {
+        match Server::bind(config)
+        {
+            Ok(server) => server,
+            Err(why) => panic!("Cannot bind server socket: {}", why),
+        }
+    }
+
+    pub fn bind(config: ServerConfig) -> io::Result<Server>
+    {
+        if config.rooms.contains_key(DEFAULT_ROOM)
+        {
+            let why = format!("Room {} is configured by the top-level settings", DEFAULT_ROOM);
+            return Err(io::Error::new(ErrorKind::InvalidInput, why));
+        }
+        let registry = match config.players_file
+        {
+            Some(ref path) => Some(Registry::load(path).map_err(|why| io::Error::new(ErrorKind::InvalidData, why))?),
+            None => None,
+        };
+        let mut rooms: BTreeMap<String, Room> = Default::default();
+        rooms.insert(String::from(DEFAULT_ROOM), Room::new(DEFAULT_ROOM, config.default_room()));
+        for (name, room_config) in &config.rooms
+        {
+            rooms.insert(name.clone(), Room::new(name, room_config.clone()));
+        }
+        for room in rooms.values_mut()
+        {
+            if rules::by_name(&room.config.rules).is_none()
+            {
+                let why = format!("Unknown rules {} in room {}", room.config.rules, room.name);
+                return Err(io::Error::new(ErrorKind::InvalidInput, why));
+            }
+            room.load_maps().map_err(|why| io::Error::new(ErrorKind::InvalidData, why))?;
+        }
         let (sen, rec) = channel();
-        let server_port = config.server_port;
+        let listener = TcpListener::bind(("0.0.0.0", config.server_port))?;
+        let local_addr = listener.local_addr()?;
         thread::spawn(move || {
-            let listener = TcpListener::bind(("0.0.0.0", server_port)).unwrap();
             for stream in listener.incoming()
             {
                 match stream
@@ -127,47 +197,61 @@ impl Server
             }
         });
         let (sen_msg, rec_msg) = channel();
-        Server {
+        Ok(Server {
+            local_addr,
             connections: Default::default(),
             incoming_connections: rec,
             incoming_messages: rec_msg,
             message_sender: sen_msg,
             seed: 0,
             tick_time: config.tick_time_ms,
+            next_tick: Instant::now(),
             game_id: 0,
-            queue: Default::default(),
-            game_start_ticks: config.game_start_ticks,
-            game_turns: config.game_turns,
-            game_start_ticks_left: config.game_turns,
-            game: None,
-            token_rate: config.token_rate,
-        }
+            rooms,
+            base_seed: config.seed.unwrap_or_else(rand::random),
+            replay_dir: config.replay_dir,
+            registry,
+            sessions: Default::default(),
+            orphans: Default::default(),
+            reconnect_grace_ticks: config.reconnect_grace_ticks,
+        })
     }
 
-    pub fn send_overview(&mut self)
+    pub fn local_addr(&self) -> SocketAddr
     {
-        if self.game.is_none()
+        self.local_addr
+    }
+
+    fn watches(role: &ConnectionType, room: &str) -> bool
+    {
+        match *role
         {
-            error!("Trying to generate overview while there is no game");
-            return;
+            ConnectionType::Viewer(None) => true,
+            ConnectionType::Viewer(Some(ref r)) => r == room,
+            _ => false,
         }
-        let overview: Overview;
+    }
+
+    pub fn send_overview(&mut self, room: &str, game_id: u64)
+    {
+        let message: ServerMessage;
         let mut gaming_ids: HashSet<u64> = Default::default();
         {
-            let game = self.game.as_ref().unwrap();
-            let mut tokens = game.tokens.clone();
-            let mut players = game.players.clone();
-            let tokens = tokens.drain().collect();
-            let players = players.drain().map(|(_, x)| x).collect();
-            overview = Overview {
-                game_id: game.game_id,
-                turns_left: game.turns_left,
-                ms_for_turn: self.tick_time,
-                grid: game.grid.clone(),
-                tokens: tokens,
-                players: players,
+            let running = match self.rooms.get(room).and_then(|r| r.games.get(&game_id))
+            {
+                Some(r) => r,
+                None =>
+                {
+                    error!("Trying to generate overview for a non-existent game");
+                    return;
+                }
             };
-            for v in game.players.keys()
+            let mut overview = running.game.overview(self.tick_time);
+            let remaining = running.deadline.saturating_duration_since(Instant::now());
+            let deadline = SystemTime::now() + remaining;
+            overview.deadline_ms = deadline.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+            message = ServerMessage::Overview(overview);
+            for v in running.game.players.keys()
             {
                 gaming_ids.insert(*v);
             }
@@ -181,11 +265,22 @@ impl Server
         {
             match role
             {
-                ConnectionType::Viewer => self.send_data(id, &overview),
-                ConnectionType::Player(user_game_id) if gaming_ids.contains(&user_game_id) => self.send_data(id, &overview),
+                ConnectionType::Player(user_game_id) if gaming_ids.contains(&user_game_id) => self.send_data(id, &message),
+                ref r if Server::watches(r, room) => self.send_data(id, &message),
                 _ => continue,
             }
         }
+        if let ServerMessage::Overview(overview) = message
+        {
+            self.rooms
+                .get_mut(room)
+                .unwrap()
+                .games
+                .get_mut(&game_id)
+                .unwrap()
+                .replay
+                .push_frame(overview);
+        }
     }
 
     pub fn main(&mut self)
@@ -199,102 +294,398 @@ impl Server
         }
         while let Ok(x) = self.incoming_messages.try_recv()
         {
-            if let MessageResponse::Disconnected(id) = x
+            self.handle_message(x);
+        }
+        let tick_time = Duration::from_millis(self.tick_time);
+        let lobby_tick = Instant::now() >= self.next_tick;
+        if lobby_tick
+        {
+            self.next_tick = (self.next_tick + tick_time).max(Instant::now());
+            self.expire_orphans();
+        }
+        let room_names: Vec<String> = self.rooms.keys().cloned().collect();
+        for room in room_names
+        {
+            self.update_room(&room, lobby_tick);
+        }
+        let mut wake_up = self.next_tick;
+        for room in self.rooms.values()
+        {
+            for running in room.games.values()
             {
-                self.queue.remove(&id);
-                if self.game.is_some()
+                wake_up = wake_up.min(running.deadline);
+            }
+        }
+        if let Ok(x) = self.incoming_messages.recv_timeout(wake_up.saturating_duration_since(Instant::now()))
+        {
+            self.handle_message(x);
+        }
+    }
+
+    fn handle_message(&mut self, x: MessageResponse)
+    {
+        if let MessageResponse::Disconnected(id) = x
+        {
+            match self.connections.remove(&id).map(|c| c.role.clone())
+            {
+                Some(ConnectionType::Player(user_game_id)) if self.reconnect_grace_ticks > 0 =>
                 {
-                    self.game.as_mut().unwrap().players.remove(&id);
+                    info!("Keeping player #{} for {} ticks", user_game_id, self.reconnect_grace_ticks);
+                    self.orphans.insert(user_game_id, self.reconnect_grace_ticks);
                 }
-                self.connections.remove(&id);
-                info!("Closed connection #{}", id);
+                Some(ConnectionType::Player(user_game_id)) => self.remove_player(user_game_id),
+                _ => (),
             }
-            else if let MessageResponse::Mail(id, msg) = x
+            info!("Closed connection #{}", id);
+        }
+        else if let MessageResponse::Mail(id, msg) = x
+        {
+            let msg = match msg.into_text()
             {
-                let msg = match msg.into_text()
+                Ok(s) => s,
+                Err(why) =>
                 {
-                    Ok(s) => s,
-                    Err(why) =>
-                    {
-                        error!("Received garbage: {}", why);
-                        continue;
-                    }
-                };
-                let msg: ClientMessage = match serde_json::from_str(msg.as_str())
+                    error!("Received garbage: {}", why);
+                    self.send_error(id, ErrorCode::InvalidMessage, "Messages must be text");
+                    return;
+                }
+            };
+            let msg: ClientMessage = match serde_json::from_str(msg.as_str())
+            {
+                Ok(s) => s,
+                Err(why) =>
                 {
-                    Ok(s) => s,
-                    Err(why) =>
-                    {
-                        error!("Received garbage: {}", why);
-                        continue;
-                    }
-                };
-                if let ClientMessage::HandShake(role) = msg
+                    error!("Received garbage: {}", why);
+                    self.send_error(id, ErrorCode::InvalidMessage, &format!("Invalid message: {}", why));
+                    return;
+                }
+            };
+            if let ClientMessage::HandShake(role) = msg
+            {
+                self.handle_accept(id, role, false);
+            }
+            else if let ClientMessage::Command(command) = msg
+            {
+                self.handle_command(id, command);
+            }
+            else
+            {
+                unreachable!();
+            }
+        }
+        else
+        {
+            unreachable!();
+        }
+    }
+
+    fn remove_player(&mut self, user_game_id: u64)
+    {
+        for room in self.rooms.values_mut()
+        {
+            room.queue.retain(|p| p.id != user_game_id);
+            for running in room.games.values_mut()
+            {
+                if running.game.players.get(&user_game_id).map(|p| p.active).unwrap_or(false)
                 {
-                    self.handle_accept(id, role, false);
+                    info!("Player #{} left game #{}", user_game_id, running.game.game_id);
+                    running.game.leave(user_game_id);
+                    running.replay.record(ReplayEvent::Leave(user_game_id));
                 }
-                else if let ClientMessage::Command(command) = msg
+            }
+        }
+        self.orphans.remove(&user_game_id);
+        self.sessions.retain(|_, s| s.player_id != user_game_id);
+    }
+
+    fn expire_orphans(&mut self)
+    {
+        let mut expired: Vec<u64> = Vec::new();
+        for (user_game_id, ticks_left) in &mut self.orphans
+        {
+            if *ticks_left == 0
+            {
+                expired.push(*user_game_id);
+            }
+            else
+            {
+                *ticks_left -= 1;
+            }
+        }
+        for user_game_id in expired
+        {
+            info!("Session of player #{} expired", user_game_id);
+            self.remove_player(user_game_id);
+        }
+    }
+
+    fn resume_session(&mut self, id: u64, token: &str) -> bool
+    {
+        let (user_game_id, name, room, team) = match self.sessions.get(token)
+        {
+            Some(s) => (s.player_id, s.name.clone(), s.room.clone(), s.team.clone()),
+            None => return false,
+        };
+        if !self.connections.contains_key(&id)
+        {
+            return true;
+        }
+        for conn in self.connections.values_mut()
+        {
+            if conn.role == ConnectionType::Player(user_game_id)
+            {
+                conn.role = ConnectionType::Unknown;
+            }
+        }
+        self.connections.get_mut(&id).unwrap().role = ConnectionType::Player(user_game_id);
+        self.orphans.remove(&user_game_id);
+        if self.find_player(user_game_id).is_none()
+        {
+            self.rooms
+                .get_mut(&room)
+                .unwrap()
+                .enqueue(Player { team, ..Player::new(user_game_id, name.clone()) });
+        }
+        info!("Player reconnected: {} (room {})", name, room);
+        let ack = HandshakeAck {
+            protocol_version: PROTOCOL_VERSION,
+            player_id: Some(user_game_id),
+            session: Some(String::from(token)),
+        };
+        self.send_data(id, &ServerMessage::HandshakeAck(ack));
+        true
+    }
+
+    fn update_room(&mut self, room: &str, lobby_tick: bool)
+    {
+        let tick_time = Duration::from_millis(self.tick_time);
+        let game_ids: Vec<u64> = self.rooms[room].games.keys().cloned().collect();
+        for game_id in game_ids
+        {
+            if self.rooms[room].games[&game_id].game.finished()
+            {
+                self.end_game(room, game_id);
+                continue;
+            }
+            {
+                let now = Instant::now();
+                let running = self.rooms.get_mut(room).unwrap().games.get_mut(&game_id).unwrap();
+                if now < running.deadline && !running.game.all_submitted()
                 {
-                    self.handle_command(id, command);
+                    continue;
                 }
-                else
+                running.game.tick();
+                running.deadline = running.deadline.min(now) + tick_time;
+            }
+            self.send_overview(room, game_id);
+        }
+        if !lobby_tick
+        {
+            return;
+        }
+        match self.rooms.get_mut(room).unwrap().countdown()
+        {
+            Countdown::Idle => (),
+            Countdown::Waiting(ticks_left) =>
+            {
+                info!("Game staring in {} ticks in room {}", ticks_left, room);
+                let message = ServerMessage::GameStarting { ticks_left };
+                for id in self.lobby_connections(room, false)
                 {
-                    unreachable!();
+                    self.send_data(id, &message);
                 }
             }
-            else
+            Countdown::Start(matches) =>
             {
-                unreachable!();
+                for players in matches
+                {
+                    self.start_game(room, players);
+                }
             }
         }
-        if self.game.is_none() && self.game_start_ticks_left > 0 && self.queue.len() >= 2
+        self.send_lobby_status(room);
+    }
+
+    fn start_game(&mut self, room: &str, queued: Vec<Player>)
+    {
+        info!("Game started with {} players in room {}", queued.len(), room);
+        let mut players: BTreeMap<u64, Player> = Default::default();
+        for player in queued
+        {
+            players.insert(player.id, player);
+        }
+        let game_id = self.game_id;
+        self.game_id += 1;
+        let seed = game_seed(self.base_seed, game_id);
+        info!("Game #{} uses seed {}", game_id, seed);
+        let (board, map_name) = self.rooms.get_mut(room).unwrap().next_board();
+        if let Some(name) = map_name
         {
-            info!("Game staring in {} ticks", self.game_start_ticks_left);
-            self.game_start_ticks_left -= 1;
+            info!("Game #{} is played on map {}", game_id, name);
         }
-        else if self.game.is_none() && self.game_start_ticks_left == 0 && self.queue.len() >= 2
+        let config = {
+            let room_config = &self.rooms[room].config;
+            GameConfig {
+                turns: room_config.game_turns,
+                token_rate: room_config.token_rate,
+                seed,
+                ghost_policy: room_config.ghost_policy,
+                rules: room_config.rules.clone(),
+                board,
+                tokens: room_config.tokens.clone(),
+                power_ups: room_config.power_ups.clone(),
+            }
+        };
+        let waiting: Vec<Player> = players.values().cloned().collect();
+        team::assign(&mut players, self.rooms[room].config.teams);
+        let replay = Replay::new(game_id, config.clone(), &players);
+        let mut game = match Game::new(players, game_id, config)
+        {
+            Ok(game) => game,
+            Err(why) =>
+            {
+                error!("Cannot start game #{} in room {}: {}", game_id, room, why);
+                self.rooms.get_mut(room).unwrap().queue.extend(waiting);
+                return;
+            }
+        };
+        game.tick();
+        let running = RunningGame {
+            game,
+            replay,
+            deadline: Instant::now() + Duration::from_millis(self.tick_time),
+        };
+        self.rooms
+            .get_mut(room)
+            .unwrap()
+            .games
+            .insert(game_id, running);
+        self.send_overview(room, game_id);
+    }
+
+    fn end_game(&mut self, room: &str, game_id: u64)
+    {
+        let running = self.rooms
+            .get_mut(room)
+            .unwrap()
+            .games
+            .remove(&game_id)
+            .unwrap();
+        let ended = running.game.game_ended().unwrap();
+        info!("Game #{} ended! ({:?})", game_id, ended.reason);
+        let mut to_readd: Vec<(u64, ClientRole)> = Vec::new();
+        let mut to_notify: Vec<u64> = Vec::new();
+        for (id, conn) in &self.connections
         {
-            info!("Game started with {} players", self.queue.len());
-            let mut players: HashMap<u64, Player> = Default::default();
-            for (player_id, player_value) in self.queue.drain()
+            match conn.role
             {
-                players.insert(player_id, player_value);
+                ConnectionType::Player(user_game_id) if running.game.players.contains_key(&user_game_id) =>
+                {
+                    to_notify.push(*id);
+                    let info = PlayerInfo {
+                        name: running.game.players[&user_game_id].name.clone(),
+                        room: Some(String::from(room)),
+                        token: None,
+                        session: None,
+                        team: self.sessions
+                            .values()
+                            .find(|s| s.player_id == user_game_id)
+                            .and_then(|s| s.team.clone()),
+                    };
+                    to_readd.push((*id, ClientRole::Player(info)));
+                }
+                ref r if Server::watches(r, room) => to_notify.push(*id),
+                _ => continue,
             }
-            self.game = Some(Game::new(players, self.game_turns, self.game_id, self.token_rate));
-            self.game.as_mut().unwrap().tick();
-            self.send_overview();
-            self.game_id += 1;
         }
-        else if self.game.is_some() && !self.game.as_ref().unwrap().finished()
+        let message = ServerMessage::GameEnded(ended);
+        for id in to_notify
+        {
+            self.send_data(id, &message);
+        }
+        self.save_replay(&running.replay);
+        for player in to_readd
+        {
+            self.handle_accept(player.0, player.1, true);
+        }
+        // Players still away have no game left to resume
+        for user_game_id in running.game.players.keys()
         {
-            self.game.as_mut().unwrap().tick();
-            self.send_overview();
+            if self.orphans.contains_key(user_game_id)
+            {
+                info!("Session of player #{} ended with game #{}", user_game_id, game_id);
+                self.remove_player(*user_game_id);
+            }
         }
-        else if self.game.is_some() && self.game.as_ref().unwrap().finished()
+    }
+
+    fn find_player(&self, user_game_id: u64) -> Option<(String, Option<u64>)>
+    {
+        for room in self.rooms.values()
         {
-            info!("Game ended!");
-            let mut to_readd: Vec<(u64, ClientRole)> = Vec::new();
-            for (key, player) in &self.game.as_ref().unwrap().players
+            if room.is_queued(user_game_id)
             {
-                to_readd.push((*key, ClientRole::Player(PlayerInfo { name: player.name.clone() })));
+                return Some((room.name.clone(), None));
             }
-            self.game = None;
-            self.game_start_ticks_left = self.game_start_ticks;
-            for player in to_readd
+            if let Some(game_id) = room.game_of(user_game_id)
             {
-                self.handle_accept(player.0, player.1, true);
+                return Some((room.name.clone(), Some(game_id)));
             }
         }
-        thread::sleep(Duration::from_millis(self.tick_time));
+        None
+    }
+
+    fn lobby_connections(&self, room: &str, with_viewers: bool) -> Vec<u64>
+    {
+        let mut ids: Vec<u64> = Vec::new();
+        for (id, conn) in &self.connections
+        {
+            match conn.role
+            {
+                ConnectionType::Player(user_game_id) if self.rooms[room].is_queued(user_game_id) => ids.push(*id),
+                ref r if with_viewers && Server::watches(r, room) => ids.push(*id),
+                _ => continue,
+            }
+        }
+        ids
+    }
+
+    fn send_lobby_status(&mut self, room: &str)
+    {
+        if self.rooms[room].queue.is_empty()
+        {
+            return;
+        }
+        let message = ServerMessage::Lobby(self.rooms[room].lobby_status(self.tick_time));
+        for id in self.lobby_connections(room, true)
+        {
+            self.send_data(id, &message);
+        }
+    }
+
+    fn authenticate(&self, info: &PlayerInfo) -> bool
+    {
+        match self.registry
+        {
+            Some(ref registry) => registry.verify(&info.name, info.token.as_deref()),
+            None => true,
+        }
     }
 
-    #[cfg_attr(feature = "cargo-clippy", allow(map_entry))]
     fn handle_accept(&mut self, id: u64, role: ClientRole, not_interactive: bool)
     {
         match role
         {
-            ClientRole::Viewer =>
+            ClientRole::Viewer(info) =>
             {
+                if let Some(ref room) = info.room
+                {
+                    if !self.rooms.contains_key(room)
+                    {
+                        self.send_error(id, ErrorCode::UnknownRoom, "No such room");
+                        return;
+                    }
+                }
                 {
                     let v = self.connections.get_mut(&id);
                     if v.is_none()
@@ -302,24 +693,63 @@ impl Server
                         return;
                     }
                     let v = v.unwrap();
-                    v.role = ConnectionType::Viewer;
+                    v.role = ConnectionType::Viewer(info.room);
                 }
                 if !not_interactive
                 {
-                    self.send_data(id, &ServerResponse::Ok);
+                    let ack = HandshakeAck {
+                        protocol_version: PROTOCOL_VERSION,
+                        player_id: None,
+                        session: None,
+                    };
+                    self.send_data(id, &ServerMessage::HandshakeAck(ack));
                     info!("Viewer connected");
                 }
             }
             ClientRole::Player(info) =>
             {
+                if let Some(ref token) = info.session
+                {
+                    if !not_interactive && self.resume_session(id, token)
+                    {
+                        return;
+                    }
+                }
                 let mut info = info;
                 info.name = String::from(info.name.trim());
                 let user_game_id = player_hash(&info);
-                if self.queue.contains_key(&user_game_id) || "\n\r\t ".chars().any(|x| info.name.contains(x))
+                let room = info.room.clone().unwrap_or_else(|| String::from(DEFAULT_ROOM));
+                if info.name.is_empty() || "\n\r\t ".chars().any(|x| info.name.contains(x))
+                {
+                    if !not_interactive
+                    {
+                        self.send_error(id, ErrorCode::InvalidName, "Username must be non-empty and without whitespace");
+                    }
+                }
+                else if info.team.as_ref().map(|t| t.is_empty() || t == "-" || t.contains(char::is_whitespace)).unwrap_or(false)
+                {
+                    if !not_interactive
+                    {
+                        self.send_error(id, ErrorCode::InvalidName, "Team name must be non-empty and without whitespace");
+                    }
+                }
+                else if !not_interactive && !self.authenticate(&info)
+                {
+                    warn!("Authentication failed for {}", info.name);
+                    self.send_error(id, ErrorCode::AuthFailed, "Invalid credentials");
+                }
+                else if !self.rooms.contains_key(&room)
+                {
+                    if !not_interactive
+                    {
+                        self.send_error(id, ErrorCode::UnknownRoom, "No such room");
+                    }
+                }
+                else if self.find_player(user_game_id).is_some()
                 {
                     if !not_interactive
                     {
-                        self.send_data(id, &ServerResponse::Error(String::from("Username already taken")));
+                        self.send_error(id, ErrorCode::NameTaken, "Username already taken");
                     }
                 }
                 else
@@ -333,62 +763,102 @@ impl Server
                         }
                         let conn = conn.unwrap();
                         conn.role = ConnectionType::Player(user_game_id);
-                        self.queue
-                            .insert(user_game_id,
-                                    Player {
-                                        name: info.name.clone(),
-                                        id: user_game_id,
-                                        points: 0,
-                                        position: Point { x: 0, y: 0 },
-                                    });
-                        if self.game.is_none()
-                        {
-                            self.game_start_ticks_left = self.game_start_ticks;
-                        }
+                        self.rooms
+                            .get_mut(&room)
+                            .unwrap()
+                            .enqueue(Player { team: info.team.clone(), ..Player::new(user_game_id, info.name.clone()) });
                     }
                     if !not_interactive
                     {
-                        info!("Player connected: {}", info.name);
-                        self.send_data(id, &ServerResponse::Ok);
+                        info!("Player connected: {} (room {})", info.name, room);
+                        let token = format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>());
+                        self.sessions.insert(token.clone(),
+                                             Session {
+                                                 player_id: user_game_id,
+                                                 name: info.name.clone(),
+                                                 room,
+                                                 team: info.team.clone(),
+                                             });
+                        let ack = HandshakeAck {
+                            protocol_version: PROTOCOL_VERSION,
+                            player_id: Some(user_game_id),
+                            session: Some(token),
+                        };
+                        self.send_data(id, &ServerMessage::HandshakeAck(ack));
                     }
                 }
             }
         };
     }
 
-    fn handle_command(&mut self, id: u64, command: ClientCommand)
+    fn handle_command(&mut self, id: u64, command: TurnCommand)
     {
         if !self.connections.contains_key(&id)
         {
             error!("Wrong ID is trying to execute command");
             return;
         }
-        if self.game.is_none()
-        {
-            self.send_data(id, &ServerResponse::Error(String::from("No active game")));
-            return;
-        }
         match self.connections[&id].role
         {
             ConnectionType::Player(user_game_id) =>
             {
-                match self.game
-                          .as_mut()
-                          .unwrap()
-                          .action(user_game_id, command)
+                let running = match self.find_player(user_game_id)
+                {
+                    Some((room, Some(game_id))) => self.rooms.get_mut(&room).unwrap().games.get_mut(&game_id).unwrap(),
+                    _ =>
+                    {
+                        self.send_command_result(id, Err(ServerError::new(ErrorCode::NoActiveGame, "No active game")));
+                        return;
+                    }
+                };
+                if command.tick != running.game.tick
+                {
+                    let message = format!("Command for tick {}, but the current tick is {}", command.tick, running.game.tick);
+                    self.send_command_result(id, Err(ServerError::new(ErrorCode::StaleTick, &message)));
+                    return;
+                }
+                let result = running.game.action(user_game_id, command.command.clone());
+                if result.is_ok()
                 {
-                    Ok(_) => self.send_data(id, &ServerResponse::Ok),
-                    Err(s) => self.send_data(id, &ServerResponse::Error(s)),
+                    running.replay.record(ReplayEvent::Command(user_game_id, command.command));
                 }
+                self.send_command_result(id, result);
             }
             _ =>
             {
-                self.send_data(id, &ServerResponse::Error(String::from("Operation not allowed")));
+                self.send_command_result(id, Err(ServerError::new(ErrorCode::NotAllowed, "Operation not allowed")));
             }
         }
     }
 
-    fn send_data<T: ?Sized + Serialize>(&mut self, id: u64, value: &T)
+    fn save_replay(&self, replay: &Replay)
+    {
+        if let Some(ref dir) = self.replay_dir
+        {
+            match replay.save(dir)
+            {
+                Ok(path) => info!("Replay saved to {}", path.display()),
+                Err(why) => error!("Unable to save replay: {}", why),
+            }
+        }
+    }
+
+    fn send_command_result(&mut self, id: u64, result: Result<(), ServerError>)
+    {
+        let response = match result
+        {
+            Ok(_) => ServerResponse::Ok,
+            Err(why) => ServerResponse::Error(why),
+        };
+        self.send_data(id, &ServerMessage::CommandResult(response));
+    }
+
+    fn send_error(&mut self, id: u64, code: ErrorCode, message: &str)
+    {
+        self.send_data(id, &ServerMessage::Error(ServerError::new(code, message)));
+    }
+
+    fn send_data(&mut self, id: u64, value: &ServerMessage)
     {
         match serde_json::to_string(value)
         {
@@ -434,8 +904,56 @@ impl Server
     }
 }
 
+impl Default for ServerConfig
+{
+    fn default() -> ServerConfig
+    {
+        ServerConfig::new()
+    }
+}
+
 impl ServerConfig
 {
+    /// Joins `base` to the relative paths of the players file, of the maps and of the
+    /// replay directory, so that they do not depend on the working directory of the server
+    pub fn resolve_paths(&mut self, base: &Path)
+    {
+        let resolve = |path: &mut Option<String>| {
+            if let Some(ref mut p) = *path
+            {
+                *p = base.join(&*p).to_string_lossy().into_owned();
+            }
+        };
+        resolve(&mut self.players_file);
+        resolve(&mut self.map_file);
+        resolve(&mut self.maps_dir);
+        resolve(&mut self.replay_dir);
+        for room in self.rooms.values_mut()
+        {
+            resolve(&mut room.map_file);
+            resolve(&mut room.maps_dir);
+        }
+    }
+
+    pub fn default_room(&self) -> RoomConfig
+    {
+        RoomConfig {
+            game_start_ticks: self.game_start_ticks,
+            game_turns: self.game_turns,
+            token_rate: self.token_rate,
+            min_players: self.min_players,
+            max_players: self.max_players,
+            ghost_policy: self.ghost_policy,
+            rules: self.rules.clone(),
+            board: self.board.clone(),
+            tokens: self.tokens.clone(),
+            power_ups: self.power_ups.clone(),
+            teams: self.teams,
+            map_file: self.map_file.clone(),
+            maps_dir: self.maps_dir.clone(),
+        }
+    }
+
     pub fn new() -> ServerConfig
     {
         ServerConfig {
@@ -444,6 +962,251 @@ impl ServerConfig
             game_start_ticks: 60,
             game_turns: 300,
             token_rate: 2.5,
+            seed: None,
+            replay_dir: Some(String::from("replays")),
+            min_players: 2,
+            max_players: None,
+            players_file: None,
+            reconnect_grace_ticks: 20,
+            ghost_policy: GhostPolicy::Freeze,
+            rules: String::from(DEFAULT_RULES),
+            board: Default::default(),
+            tokens: Default::default(),
+            power_ups: Default::default(),
+            teams: 0,
+            map_file: None,
+            maps_dir: None,
+            rooms: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use tungstenite::client;
+    use url::Url;
+
+    fn test_server(config: ServerConfig) -> Server
+    {
+        let config = ServerConfig {
+            server_port: 0,
+            tick_time_ms: 10,
+            replay_dir: None,
+            ..config
+        };
+        Server::bind(config).unwrap()
+    }
+
+    fn connect(server: &Server) -> WebSocket<TcpStream>
+    {
+        let port = server.local_addr().port();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let url = Url::parse(&format!("ws://127.0.0.1:{}", port)).unwrap();
+        let ws = client(url, stream).unwrap();
+        ws.get_ref().set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        ws
+    }
+
+    fn send(ws: &mut WebSocket<TcpStream>, message: &ClientMessage)
+    {
+        ws.write_message(Message::text(serde_json::to_string(message).unwrap())).unwrap();
+    }
+
+    /// Runs the server until it sends a message to `ws` that is not about the lobby
+    fn receive(server: &mut Server, ws: &mut WebSocket<TcpStream>) -> ServerMessage
+    {
+        for _ in 0..500
+        {
+            server.main();
+            if let Ok(message) = ws.read_message()
+            {
+                match serde_json::from_str(&message.into_text().unwrap()).unwrap()
+                {
+                    ServerMessage::Lobby(_) |
+                    ServerMessage::GameStarting { .. } => (),
+                    message => return message,
+                }
+            }
+        }
+        panic!("No message from the server");
+    }
+
+    fn run_until<F: Fn(&Server) -> bool>(server: &mut Server, done: F)
+    {
+        for _ in 0..500
+        {
+            if done(server)
+            {
+                return;
+            }
+            server.main();
+        }
+        panic!("The server never got there");
+    }
+
+    fn join(server: &mut Server, name: &str, session: Option<&str>) -> (WebSocket<TcpStream>, HandshakeAck)
+    {
+        let mut ws = connect(server);
+        let info = PlayerInfo {
+            name: String::from(name),
+            room: None,
+            token: None,
+            session: session.map(String::from),
+            team: None,
+        };
+        send(&mut ws, &ClientMessage::HandShake(ClientRole::Player(info)));
+        match receive(server, &mut ws)
+        {
+            ServerMessage::HandshakeAck(ack) => (ws, ack),
+            other => panic!("Handshake not acknowledged: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn default_room_takes_the_server_ghost_policy()
+    {
+        let config = ServerConfig {
+            ghost_policy: GhostPolicy::RemoveTerritory,
+            ..ServerConfig::new()
+        };
+        assert_eq!(config.default_room().ghost_policy, GhostPolicy::RemoveTerritory);
+        assert_eq!(RoomConfig::default().ghost_policy, GhostPolicy::Freeze);
+    }
+
+    #[test]
+    fn session_resumes_inside_the_grace_window()
+    {
+        let mut server = test_server(ServerConfig::new());
+        let (ws, ack) = join(&mut server, "alice", None);
+        drop(ws);
+        let id = ack.player_id.unwrap();
+        run_until(&mut server, |s| s.orphans.contains_key(&id));
+        let (_ws, resumed) = join(&mut server, "alice", ack.session.as_deref());
+        assert_eq!(resumed.player_id, Some(id));
+        assert_eq!(resumed.session, ack.session);
+        assert!(server.orphans.is_empty());
+    }
+
+    #[test]
+    fn session_ends_with_the_grace_window()
+    {
+        let mut server = test_server(ServerConfig {
+                                         reconnect_grace_ticks: 1,
+                                         ..ServerConfig::new()
+                                     });
+        let (ws, ack) = join(&mut server, "alice", None);
+        drop(ws);
+        let token = ack.session.clone().unwrap();
+        run_until(&mut server, |s| !s.sessions.contains_key(&token));
+        let (_ws, fresh) = join(&mut server, "alice", Some(&token));
+        assert_ne!(fresh.session, Some(token));
+    }
+
+    #[test]
+    fn unknown_session_joins_as_a_new_player()
+    {
+        let mut server = test_server(ServerConfig::new());
+        let (_ws, ack) = join(&mut server, "alice", Some("0123456789abcdef"));
+        assert!(ack.player_id.is_some());
+        assert_ne!(ack.session, Some(String::from("0123456789abcdef")));
+        assert_eq!(server.sessions.len(), 1);
+    }
+
+    #[test]
+    fn session_ends_with_its_game()
+    {
+        let mut server = test_server(ServerConfig {
+                                         game_start_ticks: 0,
+                                         game_turns: 3,
+                                         ..ServerConfig::new()
+                                     });
+        let (ws, alice) = join(&mut server, "alice", None);
+        let (_bob_ws, bob) = join(&mut server, "bob", None);
+        let alice_id = alice.player_id.unwrap();
+        run_until(&mut server, |s| s.find_player(alice_id).map(|(_, game)| game.is_some()).unwrap_or(false));
+        drop(ws);
+        run_until(&mut server, |s| s.rooms[DEFAULT_ROOM].games.is_empty());
+        assert!(!server.sessions.contains_key(&alice.session.unwrap()));
+        assert!(server.sessions.contains_key(&bob.session.unwrap()));
+    }
+
+    #[test]
+    fn relative_paths_follow_the_config_file()
+    {
+        let mut config = ServerConfig::new();
+        config.players_file = Some(String::from("players.json"));
+        config.maps_dir = Some(String::from("/srv/maps"));
+        config.resolve_paths(Path::new("etc/dazzle"));
+        assert_eq!(config.players_file, Some(String::from("etc/dazzle/players.json")));
+        assert_eq!(config.replay_dir, Some(String::from("etc/dazzle/replays")));
+        assert_eq!(config.maps_dir, Some(String::from("/srv/maps")));
+    }
+
+    /// Starts a game between alice and bob that only ticks once both moved
+    fn started_game(server: &mut Server) -> (WebSocket<TcpStream>, WebSocket<TcpStream>)
+    {
+        let (alice, _) = join(server, "alice", None);
+        let (bob, _) = join(server, "bob", None);
+        run_until(server, |s| !s.rooms[DEFAULT_ROOM].games.is_empty());
+        for running in server.rooms.get_mut(DEFAULT_ROOM).unwrap().games.values_mut()
+        {
+            running.deadline = Instant::now() + Duration::from_secs(600);
+        }
+        (alice, bob)
+    }
+
+    fn current_tick(server: &Server) -> u64
+    {
+        server.rooms[DEFAULT_ROOM].games.values().next().unwrap().game.tick
+    }
+
+    fn command(server: &mut Server, ws: &mut WebSocket<TcpStream>, tick: u64) -> ServerResponse
+    {
+        let command = TurnCommand {
+            tick,
+            command: ClientCommand::Nothing,
+        };
+        send(ws, &ClientMessage::Command(command));
+        loop
+        {
+            if let ServerMessage::CommandResult(response) = receive(server, ws)
+            {
+                return response;
+            }
+        }
+    }
+
+    #[test]
+    fn command_for_another_tick_is_stale()
+    {
+        let mut server = test_server(ServerConfig {
+                                         game_start_ticks: 0,
+                                         ..ServerConfig::new()
+                                     });
+        let (mut alice, _bob) = started_game(&mut server);
+        let tick = current_tick(&server);
+        match command(&mut server, &mut alice, tick + 1)
+        {
+            ServerResponse::Error(why) => assert_eq!(why.code, ErrorCode::StaleTick),
+            ServerResponse::Ok => panic!("command for a future tick accepted"),
         }
+        assert_eq!(current_tick(&server), tick);
+    }
+
+    #[test]
+    fn tick_fires_once_every_active_player_moved()
+    {
+        let mut server = test_server(ServerConfig {
+                                         game_start_ticks: 0,
+                                         ..ServerConfig::new()
+                                     });
+        let (mut alice, mut bob) = started_game(&mut server);
+        let tick = current_tick(&server);
+        assert!(matches!(command(&mut server, &mut alice, tick), ServerResponse::Ok));
+        assert_eq!(current_tick(&server), tick);
+        assert!(matches!(command(&mut server, &mut bob, tick), ServerResponse::Ok));
+        run_until(&mut server, |s| current_tick(s) == tick + 1);
     }
 }
@@ -0,0 +1,217 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::Point;
+use grid::Grid;
+use rand::Rng;
+use rand::distributions::{IndependentSample, Range};
+
+const ATTEMPTS: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Symmetry
+{
+    #[default]
+    None,
+    /// Left and right halves of the board mirror each other
+    Mirror,
+    /// The board looks the same once turned upside down
+    Rotational,
+}
+
+impl Symmetry
+{
+    pub fn by_name(name: &str) -> Option<Symmetry>
+    {
+        match name
+        {
+            "none" => Some(Symmetry::None),
+            "mirror" => Some(Symmetry::Mirror),
+            "rotational" => Some(Symmetry::Rotational),
+            _ => None,
+        }
+    }
+
+    pub fn image(&self, p: &Point, rows: usize, cols: usize) -> Option<Point>
+    {
+        match *self
+        {
+            Symmetry::None => None,
+            Symmetry::Mirror => Some(Point { x: cols - 1 - p.x, y: p.y }),
+            Symmetry::Rotational => Some(Point { x: cols - 1 - p.x, y: rows - 1 - p.y }),
+        }
+    }
+}
+
+pub fn distance(a: &Point, b: &Point) -> usize
+{
+    a.x.abs_diff(b.x) + a.y.abs_diff(b.y)
+}
+
+/// Picks `count` distinct free cells, at least `min_distance` steps away from each other
+/// and from the `taken` ones. With a symmetry the cells are picked in pairs of images.
+/// On a crowded board the distance is given up first, then the symmetry; when there
+/// are fewer free cells than players it fails.
+pub fn place<R: Rng>(rng: &mut R,
+                     grid: &Grid,
+                     taken: &[Point],
+                     count: usize,
+                     min_distance: usize,
+                     symmetry: Symmetry)
+                     -> Result<Vec<Point>, String>
+{
+    let rows = grid.rows();
+    let cols = grid.cols();
+    let gen_row = Range::new(0, rows);
+    let gen_col = Range::new(0, cols);
+    let mut placed: Vec<Point> = taken.to_vec();
+    let mut spawns: Vec<Point> = Vec::new();
+    let fits = |p: &Point, placed: &[Point], min: usize| !grid.is_wall(p.x, p.y) && placed.iter().all(|q| distance(p, q) >= min.max(1));
+    while spawns.len() < count
+    {
+        let pair = symmetry != Symmetry::None && count - spawns.len() >= 2;
+        let mut found: Vec<Point> = Vec::new();
+        'search: for &(min, symmetric) in &[(min_distance, pair), (0, pair), (0, false)]
+        {
+            for _ in 0..ATTEMPTS
+            {
+                let p = Point {
+                    x: gen_col.ind_sample(rng),
+                    y: gen_row.ind_sample(rng),
+                };
+                if !fits(&p, &placed, min)
+                {
+                    continue;
+                }
+                if !symmetric
+                {
+                    found.push(p);
+                    break 'search;
+                }
+                let q = symmetry.image(&p, rows, cols).unwrap();
+                if distance(&p, &q) >= min.max(1) && fits(&q, &placed, min)
+                {
+                    found.push(p);
+                    found.push(q);
+                    break 'search;
+                }
+            }
+        }
+        if found.is_empty()
+        {
+            match (0..rows).flat_map(|y| (0..cols).map(move |x| Point { x, y })).find(|p| fits(p, &placed, 0))
+            {
+                Some(free) => found.push(free),
+                None => return Err(format!("No free cell left for {} of the players", count - spawns.len())),
+            }
+        }
+        placed.extend(found.iter().cloned());
+        spawns.extend(found);
+    }
+    Ok(spawns)
+}
+
+/// Tells whether the cells can all reach each other without crossing a wall
+pub fn connected(grid: &Grid, cells: &[Point]) -> bool
+{
+    let start = match cells.first()
+    {
+        Some(p) => p,
+        None => return true,
+    };
+    let mut seen = vec![false; grid.rows() * grid.cols()];
+    let mut stack = vec![grid.index(start.x, start.y)];
+    seen[stack[0]] = true;
+    while let Some(i) = stack.pop()
+    {
+        let p = grid.point(i);
+        let neighbours = [(p.x > 0, i.wrapping_sub(1)),
+                          (p.x + 1 < grid.cols(), i + 1),
+                          (p.y > 0, i.wrapping_sub(grid.cols())),
+                          (p.y + 1 < grid.rows(), i + grid.cols())];
+        for &(inside, n) in &neighbours
+        {
+            if !inside || seen[n]
+            {
+                continue;
+            }
+            let q = grid.point(n);
+            if !grid.is_wall(q.x, q.y)
+            {
+                seen[n] = true;
+                stack.push(n);
+            }
+        }
+    }
+    cells.iter().all(|p| seen[grid.index(p.x, p.y)])
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use rand::{Isaac64Rng, SeedableRng};
+
+    fn distinct(points: &[Point]) -> bool
+    {
+        let mut sorted = points.to_vec();
+        sorted.sort();
+        sorted.dedup();
+        sorted.len() == points.len()
+    }
+
+    #[test]
+    fn spawns_are_distinct_free_and_apart()
+    {
+        let mut grid = Grid::new(20, 20);
+        for x in 0..20
+        {
+            grid.set_wall(x, 10, true);
+        }
+        let taken = [Point { x: 0, y: 0 }];
+        let mut rng = Isaac64Rng::from_seed(&[7]);
+        let spawns = place(&mut rng, &grid, &taken, 8, 4, Symmetry::None).unwrap();
+        assert_eq!(spawns.len(), 8);
+        let all: Vec<Point> = spawns.iter().chain(taken.iter()).cloned().collect();
+        assert!(distinct(&all));
+        assert!(spawns.iter().all(|p| !grid.is_wall(p.x, p.y)));
+        for (i, p) in all.iter().enumerate()
+        {
+            assert!(all[i + 1..].iter().all(|q| distance(p, q) >= 4));
+        }
+    }
+
+    #[test]
+    fn symmetric_spawns_come_in_pairs()
+    {
+        let grid = Grid::new(10, 12);
+        for &symmetry in &[Symmetry::Mirror, Symmetry::Rotational]
+        {
+            let mut rng = Isaac64Rng::from_seed(&[3]);
+            let spawns = place(&mut rng, &grid, &[], 6, 3, symmetry).unwrap();
+            assert!(distinct(&spawns));
+            for p in &spawns
+            {
+                assert!(spawns.contains(&symmetry.image(p, 10, 12).unwrap()));
+            }
+        }
+    }
+
+    #[test]
+    fn crowded_board_gives_up_the_distance_then_fails()
+    {
+        let mut grid = Grid::new(2, 2);
+        let mut rng = Isaac64Rng::from_seed(&[1]);
+        let spawns = place(&mut rng, &grid, &[], 4, 5, Symmetry::Mirror).unwrap();
+        assert_eq!(spawns.len(), 4);
+        assert!(distinct(&spawns));
+        grid.set_wall(1, 1, true);
+        assert!(place(&mut rng, &grid, &[], 4, 0, Symmetry::None).is_err());
+    }
+}
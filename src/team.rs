@@ -0,0 +1,163 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2017 - Dario Ostuni <dario.ostuni@gmail.com>
+ *
+ */
+
+use common::*;
+use game::Game;
+use std::collections::BTreeMap;
+
+/// Puts every player without a declared team in the smallest one, so that there are
+/// at least `teams` of them; the teams made up by the server are `team-1`, `team-2`...
+pub fn assign(players: &mut BTreeMap<u64, Player>, teams: usize)
+{
+    if teams == 0
+    {
+        return;
+    }
+    let mut sizes: BTreeMap<String, usize> = Default::default();
+    for team in players.values().filter_map(|p| p.team.clone())
+    {
+        *sizes.entry(team).or_insert(0) += 1;
+    }
+    let mut n = 1;
+    while sizes.len() < teams
+    {
+        sizes.entry(format!("team-{}", n)).or_insert(0);
+        n += 1;
+    }
+    for player in players.values_mut().filter(|p| p.team.is_none())
+    {
+        let team = sizes.iter().min_by_key(|&(_, size)| *size).map(|(name, _)| name.clone()).unwrap();
+        *sizes.get_mut(&team).unwrap() += 1;
+        player.team = Some(team);
+    }
+}
+
+/// The color each player paints with: the lowest id of its team, or its own id
+pub fn colors(players: &BTreeMap<u64, Player>) -> BTreeMap<u64, u64>
+{
+    let mut leaders: BTreeMap<&str, u64> = Default::default();
+    for player in players.values()
+    {
+        if let Some(ref team) = player.team
+        {
+            leaders.entry(team).or_insert(player.id);
+        }
+    }
+    players.values()
+        .map(|p| (p.id, p.team.as_ref().map(|t| leaders[t.as_str()]).unwrap_or(p.id)))
+        .collect()
+}
+
+/// Points and cells of each team, the points being the sum of those of its players
+pub fn scores(game: &Game) -> Vec<TeamScore>
+{
+    let mut scores: BTreeMap<&str, TeamScore> = Default::default();
+    for player in game.players.values()
+    {
+        if let Some(ref team) = player.team
+        {
+            let color = game.color(player.id);
+            let score = scores.entry(team).or_insert_with(|| {
+                TeamScore {
+                    name: team.clone(),
+                    color,
+                    players: Vec::new(),
+                    points: 0,
+                    cells: game.grid.count(color) as u64,
+                }
+            });
+            score.players.push(player.id);
+            score.points += player.points;
+        }
+    }
+    scores.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use game::{GhostPolicy, test_game};
+
+    fn player(id: u64, team: Option<&str>) -> Player
+    {
+        Player { team: team.map(String::from), ..Player::new(id, format!("p{}", id)) }
+    }
+
+    /// Players 1 and 2 in team `x`, player 3 in team `y`, on a 5x5 board
+    fn teams_game() -> Game
+    {
+        let mut players: BTreeMap<u64, Player> = Default::default();
+        for &(id, team) in &[(1, "x"), (2, "x"), (3, "y")]
+        {
+            players.insert(id, player(id, Some(team)));
+        }
+        let mut game = test_game(players, Some(5), Some(5), &[(1, 1), (2, 3), (4, 4)]);
+        game.config.ghost_policy = GhostPolicy::RemoveTerritory;
+        for &(x, y) in &[(1, 1), (2, 1), (3, 1), (1, 2), (3, 2), (1, 3), (3, 3)]
+        {
+            game.grid.set(x, y, Some(1));
+        }
+        game
+    }
+    #[test]
+    fn assign_fills_the_smallest_teams()
+    {
+        let mut players: BTreeMap<u64, Player> = Default::default();
+        players.insert(1, player(1, Some("red")));
+        players.insert(2, player(2, Some("red")));
+        for id in 3..7
+        {
+            players.insert(id, player(id, None));
+        }
+        assign(&mut players, 2);
+        let team = |id: u64| players[&id].team.clone().unwrap();
+        assert_eq!(team(1), "red");
+        assert_eq!(players.values().filter(|p| team(p.id) == "red").count(), 3);
+        assert_eq!(players.values().filter(|p| team(p.id) == "team-1").count(), 3);
+        let colors = colors(&players);
+        assert_eq!(colors[&2], 1);
+        assert_eq!(colors[&4], 3);
+    }
+
+    #[test]
+    fn teammates_capture_and_cash_in_together()
+    {
+        let mut game = teams_game();
+        game.tick();
+        assert_eq!(game.grid.get(2, 3), Some(1));
+        assert_eq!(game.grid.get(2, 2), Some(1));
+        assert_eq!(game.grid.count(1), 9);
+        game.tokens.insert(Point { x: 2, y: 3 },
+                           Token {
+                               position: Point { x: 2, y: 3 },
+                               value: 2,
+                               ttl: None,
+                           });
+        game.tick();
+        assert_eq!(game.players[&2].points, 18);
+        assert_eq!(game.grid.count(1), 0);
+        let scores = scores(&game);
+        assert_eq!(scores.len(), 2);
+        assert_eq!((scores[0].name.as_str(), scores[0].points, scores[0].cells), ("x", 18, 0));
+        assert_eq!(scores[0].players, vec![1, 2]);
+        assert_eq!((scores[1].name.as_str(), scores[1].color, scores[1].cells), ("y", 3, 1));
+    }
+
+    #[test]
+    fn territory_stays_while_a_teammate_plays()
+    {
+        let mut game = teams_game();
+        game.tick();
+        game.leave(1);
+        assert_eq!(game.grid.count(1), 9);
+        game.leave(2);
+        assert_eq!(game.grid.count(1), 0);
+    }
+}